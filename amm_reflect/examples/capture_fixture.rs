@@ -0,0 +1,55 @@
+//! Records the accounts `ReflectAmm` quotes from into a fixture.
+//!
+//! ```bash
+//! cargo run --example capture_fixture -- <rpc_url> tests/fixtures/usdc_plus_mainnet.json
+//! ```
+
+use std::env;
+
+use amm_reflect::{
+    fixtures::{AccountFixture, AccountSnapshot},
+    ReflectAmm,
+};
+use anyhow::{anyhow, Context};
+use jupiter_amm_interface::Amm;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let (Some(rpc_url), Some(output)) = (args.next(), args.next()) else {
+        return Err(anyhow!("Usage: capture_fixture <rpc_url> <output.json>"));
+    };
+
+    let rpc = RpcClient::new(rpc_url.clone());
+    let pubkeys = ReflectAmm::new().get_accounts_to_update();
+    let response = rpc.get_multiple_accounts_with_commitment(
+        &pubkeys,
+        CommitmentConfig::confirmed(),
+    )?;
+
+    let accounts = pubkeys
+        .iter()
+        .zip(response.value)
+        .map(|(pubkey, account)| {
+            let account =
+                account.with_context(|| format!("Missing account {pubkey}"))?;
+            Ok(AccountSnapshot::new(*pubkey, &account))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let fixture = AccountFixture {
+        slot: response.context.slot,
+        description: format!("Captured from {rpc_url}"),
+        accounts,
+    };
+    fixture.save(&output)?;
+
+    println!(
+        "Saved {} accounts at slot {} to {}",
+        fixture.accounts.len(),
+        fixture.slot,
+        output
+    );
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Updates a `ReflectAmm` from RPC on an interval and appends the price per
+//! share to a CSV file after every update.
+//!
+//! ```bash
+//! cargo run --example record_prices -- <rpc_url> usdc_plus_prices.csv 60
+//! ```
+
+use std::{env, thread, time::Duration};
+
+use amm_reflect::{history::PriceRecorder, ReflectAmm};
+use anyhow::{anyhow, Context};
+use jupiter_amm_interface::{AccountMap, Amm, ClockRef};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    account::from_account, clock::Clock, commitment_config::CommitmentConfig,
+    sysvar,
+};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let (Some(rpc_url), Some(output)) = (args.next(), args.next()) else {
+        return Err(anyhow!(
+            "Usage: record_prices <rpc_url> <output.csv> [interval_secs]"
+        ));
+    };
+    let interval = args.next().map_or(Ok(60), |secs| secs.parse())?;
+
+    let rpc = RpcClient::new(rpc_url);
+    let clock_ref = ClockRef::default();
+    let mut amm = ReflectAmm::new().with_clock(clock_ref.clone());
+    let mut recorder = PriceRecorder::new(output);
+    loop {
+        let mut pubkeys = amm.get_accounts_to_update();
+        pubkeys.push(sysvar::clock::ID);
+        let accounts = rpc
+            .get_multiple_accounts_with_commitment(
+                &pubkeys,
+                CommitmentConfig::confirmed(),
+            )?
+            .value;
+
+        let mut account_map = AccountMap::default();
+        for (pubkey, account) in pubkeys.into_iter().zip(accounts) {
+            let account =
+                account.with_context(|| format!("Missing account {pubkey}"))?;
+            account_map.insert(pubkey, account);
+        }
+        let clock: Clock = account_map
+            .remove(&sysvar::clock::ID)
+            .as_ref()
+            .and_then(from_account)
+            .context("Invalid clock sysvar")?;
+        clock_ref.update(clock);
+
+        amm.update(&account_map)?;
+        if let Some(point) = recorder.record(&amm)? {
+            println!(
+                "Slot {}: {} USDC per share",
+                point.slot, point.price_per_share
+            );
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
@@ -0,0 +1,31 @@
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    controller::DriftUsdcController,
+    oracle::OraclePrice,
+    spot_market::{DriftSpotMarket, UserSpotPosition},
+    token::MintExtensions,
+};
+
+/// What `update` reads from each account, decoded on its own so an update
+/// only decodes the accounts that changed. A field is unset until its
+/// account first decodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodedAccounts {
+    pub controller: Option<DriftUsdcController>,
+    /// The strategy's USDC deposit, in the slot usdc-plus-exchange reads.
+    pub drift_position: Option<UserSpotPosition>,
+    pub receipt_mint: Option<ReceiptMint>,
+    pub spot_market: Option<DriftSpotMarket>,
+    /// USDC the controller holds outside Drift.
+    pub idle_usdc: Option<u64>,
+    pub oracle_price: Option<OraclePrice>,
+}
+
+/// The parts of the receipt mint quotes and swaps depend on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReceiptMint {
+    pub token_program: Pubkey,
+    pub extensions: MintExtensions,
+    pub supply: u64,
+}
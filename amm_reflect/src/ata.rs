@@ -0,0 +1,124 @@
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+
+use crate::{constants::associated_token_program, pda};
+
+/// `CreateIdempotent` in the associated token program's instruction enum.
+const CREATE_IDEMPOTENT: u8 = 1;
+
+/// A swap user's associated token accounts for USDC and the receipt token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserTokenAccounts {
+    pub owner: Pubkey,
+    pub usdc: Pubkey,
+    pub receipt: Pubkey,
+}
+
+impl UserTokenAccounts {
+    pub fn derive(
+        owner: &Pubkey,
+        usdc_mint: &Pubkey,
+        usdc_token_program: &Pubkey,
+        receipt_mint: &Pubkey,
+        receipt_token_program: &Pubkey,
+    ) -> Self {
+        UserTokenAccounts {
+            owner: *owner,
+            usdc: pda::find_associated_token_address_with_program(
+                owner,
+                usdc_mint,
+                usdc_token_program,
+            ),
+            receipt: pda::find_associated_token_address_with_program(
+                owner,
+                receipt_mint,
+                receipt_token_program,
+            ),
+        }
+    }
+}
+
+/// Creates the associated token account of `owner` for `mint`, or does
+/// nothing if it already exists.
+pub fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    let associated_token_account =
+        pda::find_associated_token_address_with_program(
+            owner,
+            mint,
+            token_program,
+        );
+    Instruction {
+        program_id: associated_token_program::ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(associated_token_account, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        token_2022_program, token_program, usdc_controller, usdc_mint,
+        usdc_plus_mint,
+    };
+
+    #[test]
+    fn test_user_token_accounts_follow_token_program() {
+        let owner = Pubkey::new_unique();
+        let legacy = UserTokenAccounts::derive(
+            &owner,
+            &usdc_mint::ID,
+            &token_program::ID,
+            &usdc_plus_mint::ID,
+            &token_program::ID,
+        );
+        assert_eq!(
+            legacy.usdc,
+            pda::find_associated_token_address(&owner, &usdc_mint::ID)
+        );
+
+        let token_2022 = UserTokenAccounts::derive(
+            &owner,
+            &usdc_mint::ID,
+            &token_program::ID,
+            &usdc_plus_mint::ID,
+            &token_2022_program::ID,
+        );
+        assert_eq!(token_2022.usdc, legacy.usdc);
+        assert_ne!(token_2022.receipt, legacy.receipt);
+    }
+
+    #[test]
+    fn test_create_associated_token_account_idempotent() {
+        let payer = Pubkey::new_unique();
+        let instruction = create_associated_token_account_idempotent(
+            &payer,
+            &usdc_controller::ID,
+            &usdc_mint::ID,
+            &token_program::ID,
+        );
+
+        assert_eq!(instruction.program_id, associated_token_program::ID);
+        assert_eq!(instruction.data, vec![1]);
+        assert!(instruction.accounts[0].is_signer);
+        // The mainnet controller ATA is a known address.
+        assert_eq!(
+            instruction.accounts[1].pubkey,
+            crate::constants::controller_usdc_ata::ID
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// How much USDC can currently flow through a strategy in each direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capacity {
+    /// USDC that can still be deposited before the Drift deposit cap.
+    pub max_deposit: u64,
+    /// USDC the controller and Drift can release for redemptions.
+    pub max_redeem: u64,
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Capacity {
+            max_deposit: u64::MAX,
+            max_redeem: u64::MAX,
+        }
+    }
+}
+
+impl Capacity {
+    pub fn max_usdc(&self, is_deposit: bool) -> u64 {
+        if is_deposit {
+            self.max_deposit
+        } else {
+            self.max_redeem
+        }
+    }
+
+    pub fn check(
+        &self,
+        is_deposit: bool,
+        usdc_amount: u64,
+    ) -> Result<(), ExceedsCapacity> {
+        let available = self.max_usdc(is_deposit);
+        if usdc_amount > available {
+            return Err(ExceedsCapacity {
+                is_deposit,
+                requested: usdc_amount,
+                available,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A quote would move more USDC than the strategy can take or release.
+/// `available` is the largest USDC amount that would go through, so routers
+/// can downcast this error and retry with a partial fill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExceedsCapacity {
+    pub is_deposit: bool,
+    pub requested: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for ExceedsCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} USDC exceeds capacity of {} USDC",
+            if self.is_deposit {
+                "Deposit"
+            } else {
+                "Redemption"
+            },
+            self.requested,
+            self.available
+        )
+    }
+}
+
+impl std::error::Error for ExceedsCapacity {}
@@ -0,0 +1,74 @@
+use std::{fmt, sync::atomic::Ordering};
+
+use jupiter_amm_interface::ClockRef;
+
+/// The router's clock, shared with every AMM through `AmmContext`.
+#[derive(Clone, Default)]
+pub struct SharedClock(ClockRef);
+
+impl SharedClock {
+    pub fn new(clock_ref: ClockRef) -> Self {
+        SharedClock(clock_ref)
+    }
+
+    pub fn slot(&self) -> u64 {
+        self.0.slot.load(Ordering::Relaxed)
+    }
+
+    pub fn unix_timestamp(&self) -> i64 {
+        self.0.unix_timestamp.load(Ordering::Relaxed)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.0.epoch.load(Ordering::Relaxed)
+    }
+}
+
+impl From<ClockRef> for SharedClock {
+    fn from(clock_ref: ClockRef) -> Self {
+        SharedClock::new(clock_ref)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedClock")
+            .field("slot", &self.slot())
+            .field("unix_timestamp", &self.unix_timestamp())
+            .finish()
+    }
+}
+
+/// When the state behind quotes was last refreshed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UpdateStamp {
+    /// Slot of the account observed longest ago.
+    pub slot: u64,
+    /// Clock time when the account received longest ago arrived.
+    pub unix_timestamp: i64,
+    /// When Drift last accrued interest on the spot market. Interest is
+    /// projected from here, not from the update.
+    pub spot_market_last_interest_ts: i64,
+}
+
+/// How far cached state lags behind the clock, both measured from the
+/// accounts the state was decoded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StateAge {
+    /// Slots since the oldest account was observed.
+    pub slots: u64,
+    /// Seconds since the oldest account arrived.
+    pub seconds: i64,
+}
+
+impl UpdateStamp {
+    pub fn age(&self, clock: &SharedClock) -> StateAge {
+        StateAge {
+            slots: clock.slot().saturating_sub(self.slot),
+            seconds: clock
+                .unix_timestamp()
+                .saturating_sub(self.unix_timestamp)
+                .max(0),
+        }
+    }
+}
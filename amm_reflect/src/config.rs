@@ -0,0 +1,433 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+use crate::{
+    constants::*, oracle::OracleGuard, pda, strategy::ReflectStrategy,
+};
+
+/// Drift's devnet USDC mint.
+const DEVNET_USDC_MINT: Pubkey =
+    pubkey!("8zGuJQqwhZafTah7Uc7Z4tXRnguqkn5KLFAP8oV6PHe2");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cluster {
+    Mainnet,
+    Devnet,
+    Localnet,
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cluster::Mainnet => write!(f, "mainnet"),
+            Cluster::Devnet => write!(f, "devnet"),
+            Cluster::Localnet => write!(f, "localnet"),
+        }
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "mainnet" | "mainnet-beta" => Ok(Cluster::Mainnet),
+            "devnet" => Ok(Cluster::Devnet),
+            "localnet" | "localhost" => Ok(Cluster::Localnet),
+            _ => Err(anyhow!("{} is not a valid Cluster", s)),
+        }
+    }
+}
+
+/// Every address the adapter needs on one cluster.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflectConfig {
+    pub cluster: Cluster,
+
+    // Programs
+    #[serde_as(as = "DisplayFromStr")]
+    pub reflect_program: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub drift_program: Pubkey,
+
+    // Reflect singletons, not derivable from a controller
+    #[serde_as(as = "DisplayFromStr")]
+    pub main: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub admin_permissions: Pubkey,
+
+    // Mints
+    #[serde_as(as = "DisplayFromStr")]
+    pub usdc_mint: Pubkey,
+
+    // Drift accounts
+    #[serde_as(as = "DisplayFromStr")]
+    pub drift_state: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub referrer_user_stats: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub referrer_user: Pubkey,
+    /// Oracle of Drift's USDC spot market, which every `DriftUsdcController`
+    /// strategy deposits into.
+    #[serde_as(as = "DisplayFromStr")]
+    pub usdc_oracle: Pubkey,
+
+    pub strategies: Vec<ReflectStrategy>,
+
+    /// Refuse to quote from state more than this many slots old.
+    #[serde(default)]
+    pub max_staleness_slots: Option<u64>,
+
+    /// When the USDC oracle takes the AMMs offline.
+    #[serde(default)]
+    pub oracle_guard: OracleGuard,
+
+    /// Reject swaps whose user token accounts are not the user's ATAs.
+    #[serde(default)]
+    pub check_user_token_accounts: bool,
+}
+
+impl ReflectConfig {
+    pub fn mainnet() -> Self {
+        ReflectConfig {
+            cluster: Cluster::Mainnet,
+            reflect_program: reflect::ID,
+            drift_program: drift::ID,
+            main: reflect_main::ID,
+            admin_permissions: admin_permissions::ID,
+            usdc_mint: usdc_mint::ID,
+            drift_state: drift_state::ID,
+            referrer_user_stats: referrer_user_stats::ID,
+            referrer_user: referrer_user::ID,
+            usdc_oracle: usdc_oracle::ID,
+            strategies: vec![ReflectStrategy::usdc_plus()],
+            max_staleness_slots: None,
+            oracle_guard: OracleGuard::default(),
+            check_user_token_accounts: false,
+        }
+    }
+
+    /// Same programs as mainnet against Drift's devnet deployment. Reflect's
+    /// singletons, the USDC oracle and the strategies differ per deployment
+    /// and have to come from an overrides file.
+    pub fn devnet() -> Self {
+        ReflectConfig {
+            cluster: Cluster::Devnet,
+            reflect_program: reflect::ID,
+            drift_program: drift::ID,
+            main: Pubkey::default(),
+            admin_permissions: Pubkey::default(),
+            usdc_mint: DEVNET_USDC_MINT,
+            drift_state: pda::find_drift_state(&drift::ID),
+            referrer_user_stats: Pubkey::default(),
+            referrer_user: Pubkey::default(),
+            usdc_oracle: Pubkey::default(),
+            strategies: vec![],
+            max_staleness_slots: None,
+            oracle_guard: OracleGuard::default(),
+            check_user_token_accounts: false,
+        }
+    }
+
+    /// A local validator started with mainnet accounts cloned in.
+    pub fn localnet() -> Self {
+        ReflectConfig {
+            cluster: Cluster::Localnet,
+            ..Self::mainnet()
+        }
+    }
+
+    pub fn for_cluster(cluster: Cluster) -> Self {
+        match cluster {
+            Cluster::Mainnet => Self::mainnet(),
+            Cluster::Devnet => Self::devnet(),
+            Cluster::Localnet => Self::localnet(),
+        }
+    }
+
+    /// Loads overrides from a `.toml` or `.json` file on top of the preset
+    /// of the cluster named in the file.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let overrides: ReflectConfigOverrides =
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("toml") => toml::from_str(&contents)?,
+                Some("json") => serde_json::from_str(&contents)?,
+                _ => bail!("Unsupported config format: {}", path.display()),
+            };
+
+        let base = Self::for_cluster(overrides.cluster);
+        let config = overrides.apply(base);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Config passed through `KeyedAccount::params`: overrides in the JSON
+    /// format of `from_file`, mainnet when there are none.
+    pub fn from_params(
+        params: Option<&serde_json::Value>,
+    ) -> anyhow::Result<Self> {
+        let Some(params) = params else {
+            return Ok(Self::mainnet());
+        };
+        let overrides: ReflectConfigOverrides =
+            serde_json::from_value(params.clone())
+                .context("Invalid Reflect params")?;
+
+        let base = Self::for_cluster(overrides.cluster);
+        let config = overrides.apply(base);
+        config.validate_accounts()?;
+        Ok(config)
+    }
+
+    /// Checks that every address a swap needs has been set.
+    pub fn validate_accounts(&self) -> anyhow::Result<()> {
+        for (name, pubkey) in [
+            ("main", self.main),
+            ("admin_permissions", self.admin_permissions),
+            ("referrer_user_stats", self.referrer_user_stats),
+            ("referrer_user", self.referrer_user),
+            ("usdc_oracle", self.usdc_oracle),
+        ] {
+            ensure!(
+                pubkey != Pubkey::default(),
+                "{}: {} is not configured",
+                self.cluster,
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks the addresses and that some strategy is configured.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.validate_accounts()?;
+        ensure!(
+            !self.strategies.is_empty(),
+            "{}: no strategies configured",
+            self.cluster
+        );
+        Ok(())
+    }
+}
+
+/// Partial config read from disk. Unset fields keep the cluster preset.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReflectConfigOverrides {
+    cluster: Cluster,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    reflect_program: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    drift_program: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    main: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    admin_permissions: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    usdc_mint: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    drift_state: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    referrer_user_stats: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    referrer_user: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    usdc_oracle: Option<Pubkey>,
+    #[serde(default)]
+    strategies: Option<Vec<ReflectStrategy>>,
+    #[serde(default)]
+    max_staleness_slots: Option<u64>,
+    #[serde(default)]
+    oracle_guard: Option<OracleGuard>,
+    #[serde(default)]
+    check_user_token_accounts: Option<bool>,
+}
+
+impl ReflectConfigOverrides {
+    fn apply(self, base: ReflectConfig) -> ReflectConfig {
+        // A different Drift deployment moves its state PDA along with it.
+        let drift_state =
+            self.drift_state
+                .unwrap_or_else(|| match self.drift_program {
+                    Some(program) => pda::find_drift_state(&program),
+                    None => base.drift_state,
+                });
+
+        ReflectConfig {
+            cluster: self.cluster,
+            reflect_program: self
+                .reflect_program
+                .unwrap_or(base.reflect_program),
+            drift_program: self.drift_program.unwrap_or(base.drift_program),
+            main: self.main.unwrap_or(base.main),
+            admin_permissions: self
+                .admin_permissions
+                .unwrap_or(base.admin_permissions),
+            usdc_mint: self.usdc_mint.unwrap_or(base.usdc_mint),
+            drift_state,
+            referrer_user_stats: self
+                .referrer_user_stats
+                .unwrap_or(base.referrer_user_stats),
+            referrer_user: self.referrer_user.unwrap_or(base.referrer_user),
+            usdc_oracle: self.usdc_oracle.unwrap_or(base.usdc_oracle),
+            strategies: self.strategies.unwrap_or(base.strategies),
+            max_staleness_slots: self
+                .max_staleness_slots
+                .or(base.max_staleness_slots),
+            oracle_guard: self.oracle_guard.unwrap_or(base.oracle_guard),
+            check_user_token_accounts: self
+                .check_user_token_accounts
+                .unwrap_or(base.check_user_token_accounts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!(
+            "reflect_config_{}_{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_mainnet_config_matches_constants() {
+        let config = ReflectConfig::mainnet();
+        assert_eq!(config.drift_state, pda::find_drift_state(&drift::ID));
+        assert!(config.validate().is_ok());
+        assert!(ReflectConfig::devnet().validate().is_err());
+    }
+
+    #[test]
+    fn test_config_from_toml_overrides() {
+        let main = Pubkey::new_unique();
+        let controller = Pubkey::new_unique();
+        let path = write_temp(
+            "local.toml",
+            &format!(
+                r#"
+cluster = "localnet"
+main = "{main}"
+max_staleness_slots = 150
+check_user_token_accounts = true
+
+[oracle_guard]
+max_depeg_bps = 50
+max_confidence_bps = 100
+
+[[strategies]]
+controller = "{controller}"
+receipt_mint = "{controller}"
+drift_sub_account_id = 0
+spot_market_index = 0
+oracle = "{oracle}"
+"#,
+                oracle = usdc_oracle::ID,
+            ),
+        );
+
+        let config = ReflectConfig::from_file(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(config.cluster, Cluster::Localnet);
+        assert_eq!(config.main, main);
+        assert_eq!(config.max_staleness_slots, Some(150));
+        assert!(config.check_user_token_accounts);
+        assert_eq!(
+            config.oracle_guard,
+            OracleGuard {
+                max_depeg_bps: 50,
+                max_confidence_bps: 100,
+            }
+        );
+        assert_eq!(config.strategies.len(), 1);
+        assert_eq!(config.strategies[0].controller, controller);
+        // Untouched fields keep the preset.
+        assert_eq!(config.admin_permissions, admin_permissions::ID);
+        assert_eq!(config.usdc_mint, usdc_mint::ID);
+    }
+
+    #[test]
+    fn test_config_from_json_overrides() {
+        let drift_program = Pubkey::new_unique();
+        let path = write_temp(
+            "local.json",
+            &format!(
+                r#"{{ "cluster": "mainnet", "drift_program": "{drift_program}" }}"#
+            ),
+        );
+
+        let config = ReflectConfig::from_file(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(config.drift_program, drift_program);
+        assert_eq!(config.drift_state, pda::find_drift_state(&drift_program));
+    }
+
+    #[test]
+    fn test_config_from_params() {
+        assert_eq!(
+            ReflectConfig::from_params(None).unwrap(),
+            ReflectConfig::mainnet()
+        );
+
+        let main = Pubkey::new_unique();
+        let params = serde_json::json!({
+            "cluster": "localnet",
+            "main": main.to_string(),
+        });
+        let config = ReflectConfig::from_params(Some(&params)).unwrap();
+        assert_eq!(config.cluster, Cluster::Localnet);
+        assert_eq!(config.main, main);
+
+        // Devnet needs every Reflect and referrer account set.
+        let mut params = serde_json::json!({ "cluster": "devnet" });
+        for name in ["main", "admin_permissions", "usdc_oracle"] {
+            params[name] = Pubkey::new_unique().to_string().into();
+        }
+        let err = ReflectConfig::from_params(Some(&params)).unwrap_err();
+        assert!(err.to_string().contains("referrer_user_stats"), "{err}");
+        for name in ["referrer_user_stats", "referrer_user"] {
+            params[name] = Pubkey::new_unique().to_string().into();
+        }
+        ReflectConfig::from_params(Some(&params)).unwrap();
+
+        let params = serde_json::json!({ "cluster": "mainnet", "foo": 1 });
+        assert!(ReflectConfig::from_params(Some(&params)).is_err());
+    }
+
+    #[test]
+    fn test_config_from_file_rejects_unknown_format() {
+        let path = write_temp("local.yaml", "cluster: localnet");
+        assert!(ReflectConfig::from_file(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
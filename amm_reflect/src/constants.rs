@@ -163,3 +163,24 @@ pub mod token_program {
         ID
     }
 }
+
+pub mod token_2022_program {
+    use super::*;
+    pub const ID: Pubkey =
+        pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+    pub fn id() -> Pubkey {
+        ID
+    }
+}
+
+pub mod associated_token_program {
+    use super::*;
+    pub const ID: Pubkey =
+        pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+    pub fn id() -> Pubkey {
+        ID
+    }
+}
+
+/// Drift spot market index of USDC.
+pub const USDC_SPOT_MARKET_INDEX: u16 = 0;
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminator of the `DriftUsdcController` account.
+pub const DRIFT_USDC_CONTROLLER_DISCRIMINATOR: [u8; 8] =
+    [148, 44, 102, 104, 86, 118, 225, 194];
+
+const BUMP_OFFSET: usize = 8;
+const INDEX_OFFSET: usize = 9;
+const RECEIPT_MINT_OFFSET: usize = 11;
+const HEADER_END: usize = RECEIPT_MINT_OFFSET + 32;
+
+/// Header of a Reflect strategy controller, the account that owns the
+/// receipt mint and acts as authority of the strategy's Drift user.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DriftUsdcController {
+    pub bump: u8,
+    /// Strategy index, also used as the Drift sub-account id.
+    pub index: u16,
+    #[serde_as(as = "DisplayFromStr")]
+    pub receipt_mint: Pubkey,
+}
+
+impl DriftUsdcController {
+    pub fn try_deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            data.len() >= HEADER_END,
+            "Controller account too short: {} bytes",
+            data.len()
+        );
+        ensure!(
+            data[..8] == DRIFT_USDC_CONTROLLER_DISCRIMINATOR,
+            "Account is not a Reflect DriftUsdcController"
+        );
+
+        let index = read_u16(data, INDEX_OFFSET);
+        let receipt_mint =
+            Pubkey::try_from(&data[RECEIPT_MINT_OFFSET..HEADER_END])
+                .map_err(|_| anyhow!("Invalid receipt mint in controller"))?;
+
+        Ok(DriftUsdcController {
+            bump: data[BUMP_OFFSET],
+            index,
+            receipt_mint,
+        })
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
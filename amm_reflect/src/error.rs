@@ -0,0 +1,111 @@
+use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::{capacity::ExceedsCapacity, oracle::OracleViolation};
+
+pub type Result<T> = std::result::Result<T, ReflectAmmError>;
+
+/// Why the adapter failed. `Amm` methods return these inside
+/// `anyhow::Error`; downcast to tell the causes apart.
+#[derive(Debug, Error)]
+pub enum ReflectAmmError {
+    #[error("Account {0} is missing from the account map")]
+    MissingAccount(Pubkey),
+
+    #[error("Account {account} is invalid: {reason}")]
+    InvalidAccount { account: Pubkey, reason: String },
+
+    #[error("Invalid mint pair: input {input} output {output}")]
+    InvalidMintPair { input: Pubkey, output: Pubkey },
+
+    #[error("Market {0} has not been updated yet")]
+    NotUpdated(Pubkey),
+
+    #[error("State is {age_slots} slots old, more than {max_slots} allowed")]
+    StaleState { age_slots: u64, max_slots: u64 },
+
+    #[error("Arithmetic overflow or division by zero")]
+    MathOverflow,
+
+    #[error("Nothing to redeem from an empty vault")]
+    EmptyVault,
+
+    #[error("Failed to compute exchange components: {0}")]
+    ExchangeComponents(String),
+
+    #[error(transparent)]
+    ExceedsCapacity(#[from] ExceedsCapacity),
+
+    #[error(transparent)]
+    OracleGuard(#[from] OracleViolation),
+
+    #[error(
+        "Token account {actual} is not the associated token account {expected}"
+    )]
+    UnexpectedTokenAccount { expected: Pubkey, actual: Pubkey },
+
+    #[error(
+        "Swap has no Reflect variant in this jupiter-amm-interface version; \
+         use ReflectAmm::get_reflect_swap_and_account_metas \
+         (feature \"custom-swap\")"
+    )]
+    UnsupportedSwapVariant,
+}
+
+impl ReflectAmmError {
+    pub fn invalid_account(account: Pubkey, reason: impl ToString) -> Self {
+        ReflectAmmError::InvalidAccount {
+            account,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Stable name of the failure cause, for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReflectAmmError::MissingAccount(_) => "missing_account",
+            ReflectAmmError::InvalidAccount { .. } => "invalid_account",
+            ReflectAmmError::InvalidMintPair { .. } => "invalid_mint_pair",
+            ReflectAmmError::NotUpdated(_) => "not_updated",
+            ReflectAmmError::StaleState { .. } => "stale_state",
+            ReflectAmmError::MathOverflow => "math_overflow",
+            ReflectAmmError::EmptyVault => "empty_vault",
+            ReflectAmmError::ExchangeComponents(_) => "exchange_components",
+            ReflectAmmError::ExceedsCapacity(_) => "exceeds_capacity",
+            ReflectAmmError::OracleGuard(_) => "oracle_guard",
+            ReflectAmmError::UnexpectedTokenAccount { .. } => {
+                "unexpected_token_account"
+            }
+            ReflectAmmError::UnsupportedSwapVariant => {
+                "unsupported_swap_variant"
+            }
+        }
+    }
+
+    /// Kind of an error returned through the `Amm` trait, `"other"` when it
+    /// did not come from this crate.
+    pub fn kind_of(error: &anyhow::Error) -> &'static str {
+        error
+            .downcast_ref::<ReflectAmmError>()
+            .map_or("other", ReflectAmmError::kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_converts_into_anyhow() {
+        let controller = Pubkey::new_unique();
+        let error: anyhow::Error =
+            ReflectAmmError::NotUpdated(controller).into();
+
+        assert_eq!(
+            error.to_string(),
+            format!("Market {controller} has not been updated yet")
+        );
+        assert_eq!(ReflectAmmError::kind_of(&error), "not_updated");
+        assert_eq!(ReflectAmmError::kind_of(&anyhow::anyhow!("boom")), "other");
+    }
+}
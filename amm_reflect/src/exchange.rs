@@ -0,0 +1,100 @@
+//! `usdc-plus-exchange` conversions with typed errors, and their inverses
+//! for ExactOut quotes.
+//!
+//! The forward conversions floor their result, so the input needed for an
+//! exact output has to be rounded up or the program mints/redeems one
+//! lamport short of the quoted amount.
+
+use crate::error::{ReflectAmmError, Result};
+
+/// Receipt tokens minted for a USDC deposit, rounded down.
+pub fn compute_tokens_from_usdc(
+    usdc_amount: u64,
+    deposited_vault_value: u64,
+    effective_supply: u64,
+) -> Result<u64> {
+    usdc_plus_exchange::compute_tokens_from_usdc(
+        usdc_amount,
+        deposited_vault_value,
+        effective_supply,
+    )
+    .map_err(|_| ReflectAmmError::MathOverflow)
+}
+
+/// USDC redeemed for receipt tokens, rounded down.
+pub fn compute_usdc_from_tokens(
+    token_amount: u64,
+    deposited_vault_value: u64,
+    effective_supply: u64,
+) -> Result<u64> {
+    usdc_plus_exchange::compute_usdc_from_tokens(
+        token_amount,
+        deposited_vault_value,
+        effective_supply,
+    )
+    .map_err(|_| ReflectAmmError::MathOverflow)
+}
+
+/// Smallest USDC deposit that mints at least `token_amount` receipt tokens.
+pub fn compute_usdc_for_tokens_out(
+    token_amount: u64,
+    deposited_vault_value: u64,
+    effective_supply: u64,
+) -> Result<u64> {
+    // First deposit mints 1:1.
+    if effective_supply == 0 || deposited_vault_value == 0 {
+        return Ok(token_amount);
+    }
+    mul_div_ceil(token_amount, deposited_vault_value, effective_supply)
+}
+
+/// Smallest receipt token amount that redeems to at least `usdc_amount`.
+pub fn compute_tokens_for_usdc_out(
+    usdc_amount: u64,
+    deposited_vault_value: u64,
+    effective_supply: u64,
+) -> Result<u64> {
+    if effective_supply == 0 || deposited_vault_value == 0 {
+        return Err(ReflectAmmError::EmptyVault);
+    }
+    mul_div_ceil(usdc_amount, effective_supply, deposited_vault_value)
+}
+
+fn mul_div_ceil(a: u64, b: u64, denominator: u64) -> Result<u64> {
+    let result = (a as u128 * b as u128).div_ceil(denominator as u128);
+    u64::try_from(result).map_err(|_| ReflectAmmError::MathOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverse_is_minimal() {
+        let (tvl, supply) = (77_031_500_611, 75_986_842_576);
+        for out in [1u64, 7, 98_643_856, 100_000_000, 1_000_000_000_000] {
+            let usdc = compute_usdc_for_tokens_out(out, tvl, supply).unwrap();
+            assert!(
+                compute_tokens_from_usdc(usdc, tvl, supply).unwrap() >= out
+            );
+            assert!(
+                compute_tokens_from_usdc(usdc - 1, tvl, supply).unwrap() < out
+            );
+
+            let tokens = compute_tokens_for_usdc_out(out, tvl, supply).unwrap();
+            assert!(
+                compute_usdc_from_tokens(tokens, tvl, supply).unwrap() >= out
+            );
+            assert!(
+                compute_usdc_from_tokens(tokens - 1, tvl, supply).unwrap()
+                    < out
+            );
+        }
+    }
+
+    #[test]
+    fn test_inverse_empty_vault() {
+        assert_eq!(compute_usdc_for_tokens_out(42, 0, 0).unwrap(), 42);
+        assert!(compute_tokens_for_usdc_out(42, 0, 0).is_err());
+    }
+}
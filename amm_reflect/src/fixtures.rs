@@ -0,0 +1,205 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jupiter_amm_interface::AccountMap;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+/// One account as recorded in a fixture, with base64 data.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub data: String,
+}
+
+impl AccountSnapshot {
+    pub fn new(pubkey: Pubkey, account: &Account) -> Self {
+        AccountSnapshot {
+            pubkey,
+            owner: account.owner,
+            lamports: account.lamports,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: STANDARD.encode(&account.data),
+        }
+    }
+
+    pub fn to_account(&self) -> anyhow::Result<Account> {
+        Ok(Account {
+            lamports: self.lamports,
+            data: STANDARD
+                .decode(&self.data)
+                .with_context(|| format!("Invalid data for {}", self.pubkey))?,
+            owner: self.owner,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        })
+    }
+}
+
+/// Accounts captured together at one slot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountFixture {
+    pub slot: u64,
+    #[serde(default)]
+    pub description: String,
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+impl AccountFixture {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid fixture {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn to_account_map(&self) -> anyhow::Result<AccountMap> {
+        let mut map = AccountMap::default();
+        for snapshot in &self.accounts {
+            map.insert(snapshot.pubkey, snapshot.to_account()?);
+        }
+        Ok(map)
+    }
+}
+
+/// Accounts of several fixtures. An account in more than one takes the
+/// data of the last, so recorded snapshots can follow synthetic ones.
+pub fn load_account_map<P: AsRef<Path>>(
+    paths: &[P],
+) -> anyhow::Result<AccountMap> {
+    let mut map = AccountMap::default();
+    for path in paths {
+        map.extend(AccountFixture::load(path)?.to_account_map()?);
+    }
+    Ok(map)
+}
+
+/// Keys of the USDC+ strategy in `usdc_plus_local.json`, from a local
+/// Reflect deployment. Its Drift user and controller USDC ATA are derived
+/// from the controller.
+#[cfg(test)]
+pub(crate) mod usdc_plus_local {
+    use solana_sdk::{pubkey, pubkey::Pubkey};
+
+    /// Authority of the strategy's Drift user and of its receipt mint.
+    pub const CONTROLLER: Pubkey =
+        pubkey!("6YpkQCbhkCmYs5ow1daQSnVuRh3J2WiuPCXYE5dThJY2");
+    pub const RECEIPT_MINT: Pubkey =
+        pubkey!("EFeoDQfCf2LaP1n4DuUk9AJVu2zj5f2PLnf25uLuP1kV");
+    pub const DRIFT_USER: Pubkey =
+        pubkey!("4dmvjMRLFzoGUbWVhChVui9g2C9Qk7GqXqzMfk2yzLdV");
+    pub const CONTROLLER_USDC_ATA: Pubkey =
+        pubkey!("65HJ88i94kaAS8AGeGyZjuBRuALDAx9RoaMQs3Trs2W");
+}
+
+/// The USDC+ fixtures tests run against. Synthetic accounts come first, so
+/// a recorded snapshot of them wins.
+#[cfg(test)]
+pub(crate) fn usdc_plus_account_map() -> AccountMap {
+    load_account_map(&[
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/usdc_plus_synthetic.json"
+        ),
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/usdc_plus_local.json"
+        ),
+    ])
+    .unwrap()
+}
+
+/// The AMM of the recorded controller on a local cluster, not updated yet.
+#[cfg(test)]
+pub(crate) fn usdc_plus_amm() -> crate::ReflectAmm {
+    use crate::{config::ReflectConfig, controller::DriftUsdcController};
+
+    let controller = DriftUsdcController::try_deserialize(
+        &usdc_plus_account_map()[&usdc_plus_local::CONTROLLER].data,
+    )
+    .unwrap();
+    crate::ReflectAmm::from_controller(
+        &ReflectConfig::localnet(),
+        usdc_plus_local::CONTROLLER,
+        &controller,
+        Default::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usdc_plus_local_accounts_belong_together() {
+        use crate::{
+            constants::{drift, usdc_mint},
+            controller::DriftUsdcController,
+            pda,
+        };
+        use usdc_plus_local::*;
+
+        let account_map = usdc_plus_account_map();
+        let controller = DriftUsdcController::try_deserialize(
+            &account_map[&CONTROLLER].data,
+        )
+        .unwrap();
+        assert_eq!(controller.receipt_mint, RECEIPT_MINT);
+
+        // The controller signs for the Drift user and mints receipt tokens.
+        let drift_user = &account_map[&DRIFT_USER].data;
+        assert_eq!(drift_user[8..40], CONTROLLER.to_bytes());
+        let receipt_mint = &account_map[&RECEIPT_MINT].data;
+        assert_eq!(receipt_mint[4..36], CONTROLLER.to_bytes());
+
+        assert_eq!(
+            DRIFT_USER,
+            pda::find_drift_user(&drift::ID, &CONTROLLER, 0)
+        );
+        assert_eq!(
+            CONTROLLER_USDC_ATA,
+            pda::find_associated_token_address(&CONTROLLER, &usdc_mint::ID)
+        );
+    }
+
+    #[test]
+    fn test_fixture_roundtrip() {
+        let pubkey = Pubkey::new_unique();
+        let account = Account {
+            lamports: 42,
+            data: vec![1, 2, 3, 255],
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: u64::MAX,
+        };
+        let fixture = AccountFixture {
+            slot: 7,
+            description: String::new(),
+            accounts: vec![AccountSnapshot::new(pubkey, &account)],
+        };
+
+        let json = serde_json::to_string(&fixture).unwrap();
+        let decoded: AccountFixture = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, fixture);
+
+        let map = decoded.to_account_map().unwrap();
+        assert_eq!(map.get(&pubkey), Some(&account));
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal,
+};
+
+use crate::{spot_market::ONE_YEAR, ReflectAmm};
+
+const CSV_HEADER: &str =
+    "slot,unix_timestamp,protocol_tvl,effective_supply,price_per_share";
+
+/// Price per share of the receipt token after one `update`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PricePoint {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub protocol_tvl: u64,
+    pub effective_supply: u64,
+    pub price_per_share: Decimal,
+}
+
+impl PricePoint {
+    /// The price as of the last `update`, valued like quotes value it with
+    /// interest projected to the update's time, so that it matches the
+    /// recorded slot and time. `None` before the first update.
+    pub fn from_amm(amm: &ReflectAmm) -> Option<Self> {
+        let stamp = amm.last_update?;
+        Some(PricePoint {
+            slot: stamp.slot,
+            unix_timestamp: stamp.unix_timestamp,
+            protocol_tvl: amm.protocol_tvl_at(stamp.unix_timestamp),
+            effective_supply: amm.effective_supply,
+            price_per_share: amm.price_per_share_at(stamp.unix_timestamp),
+        })
+    }
+
+    fn to_csv_row(self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.slot,
+            self.unix_timestamp,
+            self.protocol_tvl,
+            self.effective_supply,
+            self.price_per_share
+        )
+    }
+
+    fn from_csv_row(row: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = row.split(',').collect();
+        let [slot, unix_timestamp, protocol_tvl, effective_supply, pps] =
+            fields[..]
+        else {
+            bail!("Expected 5 fields, got {}", fields.len());
+        };
+        Ok(PricePoint {
+            slot: slot.parse()?,
+            unix_timestamp: unix_timestamp.parse()?,
+            protocol_tvl: protocol_tvl.parse()?,
+            effective_supply: effective_supply.parse()?,
+            price_per_share: pps.parse()?,
+        })
+    }
+}
+
+/// Appends a `PricePoint` per update to a CSV file.
+#[derive(Clone, Debug)]
+pub struct PriceRecorder {
+    path: PathBuf,
+    last_row: LastRow,
+}
+
+/// What the recorder knows of the last row in its file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LastRow {
+    /// The file has not been read yet.
+    Unread,
+    /// The file has no rows.
+    None,
+    Slot(u64),
+}
+
+impl PriceRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        PriceRecorder {
+            path: path.into(),
+            last_row: LastRow::Unread,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the state of the last `update`. Does nothing before the
+    /// first update or if the slot is already recorded last. Only the first
+    /// call reads the file; later ones trust the slot they wrote.
+    pub fn record(
+        &mut self,
+        amm: &ReflectAmm,
+    ) -> anyhow::Result<Option<PricePoint>> {
+        let Some(point) = PricePoint::from_amm(amm) else {
+            return Ok(None);
+        };
+        if self.last_row == LastRow::Unread {
+            self.last_row = match self.load()?.last() {
+                Some(last) => LastRow::Slot(last.slot),
+                None => LastRow::None,
+            };
+        }
+        if let LastRow::Slot(last_slot) = self.last_row {
+            if last_slot >= point.slot {
+                return Ok(None);
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| {
+                format!("Failed to open {}", self.path.display())
+            })?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{CSV_HEADER}")?;
+        }
+        writeln!(file, "{}", point.to_csv_row())?;
+        self.last_row = LastRow::Slot(point.slot);
+        Ok(Some(point))
+    }
+
+    /// Recorded points, oldest first. A missing file has none.
+    pub fn load(&self) -> anyhow::Result<Vec<PricePoint>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read {}", self.path.display())
+                })
+            }
+        };
+
+        let mut lines = contents.lines().enumerate();
+        if let Some((_, header)) = lines.next() {
+            ensure!(
+                header == CSV_HEADER,
+                "Unexpected header in {}",
+                self.path.display()
+            );
+        }
+        lines
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| {
+                PricePoint::from_csv_row(line).with_context(|| {
+                    format!(
+                        "Invalid row {} in {}",
+                        index + 1,
+                        self.path.display()
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Yield of the receipt token between the first point at or after `start`
+/// and the last at or before `end`, compounded to a year. `None` if the
+/// window has fewer than two points apart in time.
+pub fn apy(points: &[PricePoint], start: i64, end: i64) -> Option<Decimal> {
+    let first = points.iter().find(|point| point.unix_timestamp >= start)?;
+    let last = points.iter().rfind(|point| point.unix_timestamp <= end)?;
+    let elapsed = last.unix_timestamp - first.unix_timestamp;
+    if elapsed <= 0 || first.price_per_share.is_zero() {
+        return None;
+    }
+
+    let growth = (last.price_per_share / first.price_per_share).to_f64()?;
+    let periods = ONE_YEAR as f64 / elapsed as f64;
+    Decimal::from_f64(growth.powf(periods) - 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400;
+
+    fn point(slot: u64, unix_timestamp: i64, tvl: u64) -> PricePoint {
+        PricePoint {
+            slot,
+            unix_timestamp,
+            protocol_tvl: tvl,
+            effective_supply: 1_000_000,
+            price_per_share: crate::state::exchange_rate(tvl, 1_000_000),
+        }
+    }
+
+    #[test]
+    fn test_price_point_csv_roundtrip() {
+        let point = point(7, 1_700_000_000, 1_013_700);
+        assert_eq!(point.to_csv_row(), "7,1700000000,1013700,1000000,1.0137");
+        assert_eq!(
+            PricePoint::from_csv_row(&point.to_csv_row()).unwrap(),
+            point
+        );
+        assert!(PricePoint::from_csv_row("7,1700000000").is_err());
+    }
+
+    #[test]
+    fn test_price_point_values_like_quotes() {
+        use std::sync::atomic::Ordering;
+
+        use jupiter_amm_interface::{Amm, ClockRef};
+
+        use crate::fixtures::{usdc_plus_account_map, usdc_plus_amm};
+
+        // An update an hour after Drift last accrued interest.
+        let clock_ref = ClockRef::default();
+        let mut amm = usdc_plus_amm().with_clock(clock_ref.clone());
+        amm.update(&usdc_plus_account_map()).unwrap();
+        let update_time = amm.spot_market.last_interest_ts + 3_600;
+        clock_ref
+            .unix_timestamp
+            .store(update_time, Ordering::Relaxed);
+        amm.update(&usdc_plus_account_map()).unwrap();
+
+        let point = PricePoint::from_amm(&amm).unwrap();
+        assert_eq!(point.unix_timestamp, update_time);
+        assert_eq!(point.protocol_tvl, amm.projected_protocol_tvl());
+        assert!(point.protocol_tvl > amm.protocol_tvl);
+        assert_eq!(point.price_per_share, amm.price_per_share());
+    }
+
+    #[test]
+    fn test_price_recorder_appends_updates() {
+        let path = std::env::temp_dir()
+            .join(format!("amm_reflect_history_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut recorder = PriceRecorder::new(&path);
+
+        let mut amm = ReflectAmm::new();
+        assert_eq!(recorder.record(&amm).unwrap(), None);
+        assert!(recorder.load().unwrap().is_empty());
+
+        amm.protocol_tvl = 1_050_000;
+        amm.effective_supply = 1_000_000;
+        for slot in [10, 10, 20] {
+            amm.last_update = Some(crate::clock::UpdateStamp {
+                slot,
+                unix_timestamp: slot as i64 * 100,
+                spot_market_last_interest_ts: 0,
+            });
+            recorder.record(&amm).unwrap();
+        }
+
+        let history = recorder.load().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].slot, 20);
+        assert_eq!(history[1].price_per_share, Decimal::new(105, 2));
+
+        // A new recorder picks up after the last row of the file.
+        let mut recorder = PriceRecorder::new(&path);
+        assert_eq!(recorder.record(&amm).unwrap(), None);
+        assert_eq!(recorder.load().unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_apy() {
+        let points = [
+            point(1, 0, 1_000_000),
+            point(2, DAY, 1_000_100),
+            point(3, 365 * DAY, 1_050_000),
+        ];
+
+        // A year of 5% growth is 5% APY.
+        let yearly = apy(&points, 0, 365 * DAY).unwrap();
+        assert!((yearly - Decimal::new(5, 2)).abs() < Decimal::new(1, 9));
+
+        // 1 bp a day compounds to about 3.72%.
+        let daily = apy(&points, 0, DAY).unwrap();
+        assert!((daily - Decimal::new(372, 4)).abs() < Decimal::new(1, 4));
+
+        assert_eq!(apy(&points, DAY, DAY), None);
+        assert_eq!(apy(&points, 2 * DAY, 3 * DAY), None);
+        assert_eq!(apy(&[], 0, DAY), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{fmt, fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use solana_sdk::instruction::AccountMeta;
+
+use crate::types::REFLECT_SWAP_ACCOUNT_NAMES;
+
+/// The parts of an Anchor IDL needed to check instruction layouts. Reads
+/// both the current format and the legacy `isMut`/`isSigner` one.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Idl {
+    #[serde(default)]
+    pub address: Option<String>,
+    pub instructions: Vec<IdlInstruction>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlInstruction {
+    pub name: String,
+    #[serde(default)]
+    pub discriminator: Vec<u8>,
+    pub accounts: Vec<IdlAccount>,
+    #[serde(default)]
+    pub args: Vec<IdlField>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlAccount {
+    pub name: String,
+    #[serde(default, alias = "isMut")]
+    pub writable: bool,
+    #[serde(default, alias = "isSigner")]
+    pub signer: bool,
+    #[serde(default, alias = "isOptional")]
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: serde_json::Value,
+}
+
+impl Idl {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid IDL {}", path.display()))
+    }
+
+    pub fn instruction(&self, name: &str) -> anyhow::Result<&IdlInstruction> {
+        self.instructions
+            .iter()
+            .find(|instruction| instruction.name == name)
+            .ok_or_else(|| anyhow!("IDL has no instruction {}", name))
+    }
+}
+
+/// A difference between account metas and the IDL that would fail on-chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutMismatch {
+    TooFewAccounts {
+        expected: usize,
+        actual: usize,
+    },
+    Name {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+    NotWritable(String),
+    NotSigner(String),
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutMismatch::TooFewAccounts { expected, actual } => write!(
+                f,
+                "{} accounts where the IDL needs {}",
+                actual, expected
+            ),
+            LayoutMismatch::Name {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Account #{} is {} where the IDL has {}",
+                index + 1,
+                actual,
+                expected
+            ),
+            LayoutMismatch::NotWritable(name) => {
+                write!(f, "{} must be writable", name)
+            }
+            LayoutMismatch::NotSigner(name) => {
+                write!(f, "{} must sign", name)
+            }
+        }
+    }
+}
+
+/// How account metas compare with an IDL instruction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayoutReport {
+    pub mismatches: Vec<LayoutMismatch>,
+    /// Writable although the IDL only reads them. Valid, but the extra
+    /// write locks serialize unrelated transactions.
+    pub excess_writable: Vec<String>,
+    pub excess_signer: Vec<String>,
+    /// Accounts past the IDL's list, passed as remaining accounts.
+    pub remaining: usize,
+}
+
+impl LayoutReport {
+    /// The program would accept the accounts.
+    pub fn is_compatible(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Compares the accounts of a Reflect mint or redeem with `instruction`.
+pub fn check_reflect_swap(
+    instruction: &IdlInstruction,
+    metas: &[AccountMeta],
+) -> LayoutReport {
+    check_account_metas(instruction, &REFLECT_SWAP_ACCOUNT_NAMES, metas)
+}
+
+/// Compares `metas`, whose accounts are called `names`, with the accounts
+/// of `instruction` position by position.
+pub fn check_account_metas(
+    instruction: &IdlInstruction,
+    names: &[&str],
+    metas: &[AccountMeta],
+) -> LayoutReport {
+    let mut report = LayoutReport::default();
+    let expected = instruction.accounts.len();
+    if metas.len() < expected || names.len() < expected {
+        report.mismatches.push(LayoutMismatch::TooFewAccounts {
+            expected,
+            actual: metas.len().min(names.len()),
+        });
+        return report;
+    }
+
+    for (index, (account, (name, meta))) in instruction
+        .accounts
+        .iter()
+        .zip(names.iter().zip(metas))
+        .enumerate()
+    {
+        if account.name != *name {
+            report.mismatches.push(LayoutMismatch::Name {
+                index,
+                expected: account.name.clone(),
+                actual: name.to_string(),
+            });
+            continue;
+        }
+        match (account.writable, meta.is_writable) {
+            (true, false) => report
+                .mismatches
+                .push(LayoutMismatch::NotWritable(account.name.clone())),
+            (false, true) => report.excess_writable.push(account.name.clone()),
+            _ => {}
+        }
+        match (account.signer, meta.is_signer) {
+            (true, false) => report
+                .mismatches
+                .push(LayoutMismatch::NotSigner(account.name.clone())),
+            (false, true) => report.excess_signer.push(account.name.clone()),
+            _ => {}
+        }
+    }
+    report.remaining = metas.len() - expected;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+
+    #[test]
+    fn test_check_account_metas_reports_mismatches() {
+        let idl: Idl = serde_json::from_str(
+            r#"{
+                "instructions": [{
+                    "name": "swap",
+                    "accounts": [
+                        { "name": "user", "isMut": true, "isSigner": true },
+                        { "name": "pool", "isMut": false, "isSigner": false }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let instruction = idl.instruction("swap").unwrap();
+        let user = Pubkey::new_unique();
+        let pool = Pubkey::new_unique();
+
+        let report = check_account_metas(
+            instruction,
+            &["user", "pool"],
+            &[
+                AccountMeta::new_readonly(user, true),
+                AccountMeta::new(pool, false),
+            ],
+        );
+        assert_eq!(
+            report.mismatches,
+            vec![LayoutMismatch::NotWritable("user".to_owned())]
+        );
+        assert_eq!(report.excess_writable, vec!["pool".to_owned()]);
+
+        let report = check_account_metas(
+            instruction,
+            &["pool", "user"],
+            &[AccountMeta::new(pool, false), AccountMeta::new(user, true)],
+        );
+        assert_eq!(report.mismatches.len(), 2);
+
+        let report = check_account_metas(
+            instruction,
+            &["user"],
+            &[AccountMeta::new(user, true)],
+        );
+        assert_eq!(
+            report.mismatches,
+            vec![LayoutMismatch::TooFewAccounts {
+                expected: 2,
+                actual: 1,
+            }]
+        );
+    }
+}
@@ -1,14 +1,57 @@
-use anyhow::anyhow;
+use std::collections::HashMap;
+
+use accounts::{DecodedAccounts, ReceiptMint};
+use ata::UserTokenAccounts;
+use capacity::Capacity;
+use clock::{SharedClock, StateAge, UpdateStamp};
+use config::ReflectConfig;
 use constants::*;
+use controller::DriftUsdcController;
+use error::ReflectAmmError;
 use jupiter_amm_interface::{
-    try_get_account_data, AccountMap, Amm, AmmContext, KeyedAccount, Quote,
-    QuoteParams, Swap, SwapAndAccountMetas, SwapMode, SwapParams,
+    AccountMap, Amm, AmmContext, ClockRef, KeyedAccount, Quote, QuoteParams,
+    SwapAndAccountMetas, SwapMode, SwapParams,
+};
+use oracle::{OracleGuard, OraclePrice, OracleViolation};
+use rust_decimal::Decimal;
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
 };
-use solana_sdk::pubkey::Pubkey;
+use spot_market::{
+    clamp_u64, find_user_spot_position, DriftSpotMarket, UserSpotPosition,
+};
+use state::ReflectState;
+use strategy::ReflectStrategy;
+#[cfg(feature = "custom-swap")]
+use swap::ReflectSwapAndAccountMetas;
+use swap::ReflectSwapVariant;
+use token::{MintExtensions, TransferFee};
 use types::ReflectSwap;
 
+pub mod accounts;
+pub mod ata;
+pub mod capacity;
+pub mod clock;
+pub mod config;
 pub mod constants;
+pub mod controller;
+pub mod error;
+pub mod exchange;
+pub mod fixtures;
+pub mod history;
+pub mod idl;
+pub mod oracle;
+pub mod pda;
+pub mod spot_market;
+pub mod state;
+pub mod strategy;
+pub mod swap;
+pub mod token;
 mod types;
+#[cfg(feature = "updater")]
+pub mod updater;
 
 #[derive(Clone, Debug, Default)]
 pub struct ReflectAmm {
@@ -17,6 +60,7 @@ pub struct ReflectAmm {
 
     // Core accounts
     pub main: Pubkey,
+    pub usdc_mint: Pubkey,
     pub usdc_plus_controller: Pubkey,
     pub admin_permissions: Pubkey,
     pub usdc_plus_mint: Pubkey,
@@ -36,9 +80,52 @@ pub struct ReflectAmm {
     pub drift_usdc_spot_market: Pubkey,
     pub usdc_oracle: Pubkey,
 
+    // Receipt token
+    /// Owner of the receipt mint, SPL Token or Token-2022.
+    pub receipt_token_program: Pubkey,
+    pub receipt_mint_extensions: MintExtensions,
+
     // Rates
     pub protocol_tvl: u64,
     pub effective_supply: u64,
+    pub capacity: Capacity,
+
+    // Interest accrual
+    pub spot_market: DriftSpotMarket,
+    /// The strategy's scaled USDC deposit in the Drift spot market.
+    pub drift_scaled_balance: u64,
+
+    // Oracle
+    /// Unset until `update` succeeds. Only the guard reads it: TVL stays in
+    /// USDC token units, as usdc-plus-exchange computes it without the
+    /// oracle.
+    pub oracle_price: Option<OraclePrice>,
+    pub oracle_guard: OracleGuard,
+
+    // Freshness
+    pub clock: SharedClock,
+    /// Unset until `update` succeeds; quotes are refused before that.
+    pub last_update: Option<UpdateStamp>,
+    pub max_staleness_slots: Option<u64>,
+    /// Last data of each account `update` reads, so accounts can arrive in
+    /// batches. New data replaces it only if it decodes, on its own and
+    /// into the exchange components.
+    pub account_cache: AccountMap,
+    /// What the cached accounts decode to.
+    pub decoded: DecodedAccounts,
+    /// Slot each cached account was last observed at. The oldest stamps
+    /// the state, so an account whose feed stalls ages it.
+    pub account_slots: HashMap<Pubkey, u64>,
+    /// Clock time each cached account last arrived at.
+    pub account_times: HashMap<Pubkey, i64>,
+
+    // Snapshot
+    /// Decoded accounts from the last successful `update`.
+    pub state: Option<ReflectState>,
+
+    // Swaps
+    /// Reject swaps whose user token accounts are not the user's ATAs.
+    pub check_user_token_accounts: bool,
 }
 
 impl ReflectAmm {
@@ -49,6 +136,7 @@ impl ReflectAmm {
 
             // Core accounts
             main: reflect_main::ID,
+            usdc_mint: usdc_mint::ID,
             usdc_plus_controller: usdc_controller::ID,
             admin_permissions: admin_permissions::ID,
             usdc_plus_mint: usdc_plus_mint::ID,
@@ -68,127 +156,664 @@ impl ReflectAmm {
             drift_usdc_spot_market: usdc_spot_market::ID,
             usdc_oracle: usdc_oracle::ID,
 
+            // Receipt token
+            receipt_token_program: token_program::ID,
+            receipt_mint_extensions: MintExtensions::default(),
+
             // Rates
             protocol_tvl: 0,
             effective_supply: 0,
+            capacity: Capacity::default(),
+
+            // Interest accrual
+            spot_market: DriftSpotMarket::default(),
+            drift_scaled_balance: 0,
+
+            // Oracle
+            oracle_price: None,
+            oracle_guard: OracleGuard::default(),
+
+            // Freshness
+            clock: SharedClock::default(),
+            last_update: None,
+            max_staleness_slots: None,
+            account_cache: AccountMap::default(),
+            decoded: DecodedAccounts::default(),
+            account_slots: HashMap::new(),
+            account_times: HashMap::new(),
+
+            // Snapshot
+            state: None,
+
+            // Swaps
+            check_user_token_accounts: false,
         }
     }
-}
 
-impl Amm for ReflectAmm {
-    fn from_keyed_account(
-        _keyed_account: &KeyedAccount,
-        _amm_context: &AmmContext,
-    ) -> anyhow::Result<Self> {
-        Ok(ReflectAmm::new())
+    /// Builds the AMM for a decoded controller on the cluster of `config`,
+    /// deriving the strategy's Drift accounts from the controller key and
+    /// its strategy index.
+    pub fn from_controller(
+        config: &ReflectConfig,
+        key: Pubkey,
+        controller: &DriftUsdcController,
+        clock_ref: ClockRef,
+    ) -> Self {
+        ReflectAmm::from_config(
+            config,
+            &ReflectStrategy::from_controller(config, key, controller),
+            clock_ref,
+        )
     }
 
-    fn label(&self) -> String {
-        self.label.clone()
+    /// Builds the AMM for a mainnet strategy.
+    pub fn from_strategy(
+        strategy: &ReflectStrategy,
+        clock_ref: ClockRef,
+    ) -> Self {
+        ReflectAmm::from_config(&ReflectConfig::mainnet(), strategy, clock_ref)
     }
 
-    /// ID of reflect delta neutral.
-    fn program_id(&self) -> Pubkey {
-        self.program_id
+    /// Builds the AMM for `strategy` on the cluster described by `config`.
+    /// `clock_ref` is the router's clock, which staleness checks and
+    /// interest projection read.
+    pub fn from_config(
+        config: &ReflectConfig,
+        strategy: &ReflectStrategy,
+        clock_ref: ClockRef,
+    ) -> Self {
+        let drift_program = config.drift_program;
+        let key = strategy.controller;
+        ReflectAmm {
+            label: REFLECT_LABEL.to_owned(),
+            program_id: config.reflect_program,
+
+            // Core accounts
+            // `main` and `admin_permissions` are singletons of the Reflect
+            // deployment. The controller does not reference them and they
+            // are not PDAs of anything it holds, so they cannot be derived
+            // and come from the cluster config instead.
+            main: config.main,
+            usdc_mint: config.usdc_mint,
+            usdc_plus_controller: key,
+            admin_permissions: config.admin_permissions,
+            usdc_plus_mint: strategy.receipt_mint,
+            controller_usdc_ata: pda::find_associated_token_address(
+                &key,
+                &config.usdc_mint,
+            ),
+
+            // Drift accounts
+            drift_program,
+            drift_state: config.drift_state,
+            drift_user_stats: pda::find_drift_user_stats(&drift_program, &key),
+            usdc_plus_drift_user_acc: pda::find_drift_user(
+                &drift_program,
+                &key,
+                strategy.drift_sub_account_id,
+            ),
+            drift_spot_market_vault: pda::find_drift_spot_market_vault(
+                &drift_program,
+                strategy.spot_market_index,
+            ),
+            drift_vault: pda::find_drift_signer(&drift_program),
+            referrer_user_stats: config.referrer_user_stats,
+            referrer_user: config.referrer_user,
+
+            // Remaining accounts
+            drift_usdc_spot_market: pda::find_drift_spot_market(
+                &drift_program,
+                strategy.spot_market_index,
+            ),
+            usdc_oracle: strategy.oracle,
+
+            // Receipt token
+            receipt_token_program: token_program::ID,
+            receipt_mint_extensions: MintExtensions::default(),
+
+            // Rates
+            protocol_tvl: 0,
+            effective_supply: 0,
+            capacity: Capacity::default(),
+
+            // Interest accrual
+            spot_market: DriftSpotMarket::default(),
+            drift_scaled_balance: 0,
+
+            // Oracle
+            oracle_price: None,
+            oracle_guard: config.oracle_guard,
+
+            // Freshness
+            clock: SharedClock::new(clock_ref),
+            last_update: None,
+            max_staleness_slots: config.max_staleness_slots,
+            account_cache: AccountMap::default(),
+            decoded: DecodedAccounts::default(),
+            account_slots: HashMap::new(),
+            account_times: HashMap::new(),
+
+            // Snapshot
+            state: None,
+
+            // Swaps
+            check_user_token_accounts: config.check_user_token_accounts,
+        }
     }
 
-    // This signs to drift and holds tokens.
-    fn key(&self) -> Pubkey {
-        self.usdc_plus_controller
+    /// Reads slots and time from the router's clock instead of a detached
+    /// default one.
+    pub fn with_clock(mut self, clock_ref: ClockRef) -> Self {
+        self.clock = SharedClock::new(clock_ref);
+        self
     }
 
-    /// Mints between which you can exhcange.
-    fn get_reserve_mints(&self) -> Vec<Pubkey> {
-        vec![usdc_mint::ID, usdc_plus_mint::ID]
+    pub fn with_max_staleness_slots(mut self, max_slots: u64) -> Self {
+        self.max_staleness_slots = Some(max_slots);
+        self
     }
 
-    /// Accounts needed to generate a quote.
-    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
-        // Whatver the exchnage library needs for exchange with drift only.
-        vec![
+    pub fn with_user_token_account_checks(mut self, check: bool) -> Self {
+        self.check_user_token_accounts = check;
+        self
+    }
+
+    pub fn with_oracle_guard(mut self, oracle_guard: OracleGuard) -> Self {
+        self.oracle_guard = oracle_guard;
+        self
+    }
+
+    /// Why the oracle takes the AMM offline, `None` while it is within the
+    /// guard or before `update`.
+    pub fn oracle_violation(&self) -> Option<OracleViolation> {
+        self.oracle_price
+            .and_then(|price| self.oracle_guard.check(&price).err())
+    }
+
+    /// Age of the state quotes are priced from, `None` before `update`.
+    pub fn state_age(&self) -> Option<StateAge> {
+        self.last_update.map(|stamp| stamp.age(&self.clock))
+    }
+
+    /// TVL with the Drift interest accrued since the last update, up to the
+    /// clock's time. Quotes price from it.
+    pub fn projected_protocol_tvl(&self) -> u64 {
+        self.protocol_tvl_at(self.clock.unix_timestamp())
+    }
+
+    /// TVL with the Drift interest accrued since the last update, up to
+    /// `unix_timestamp`. usdc-plus-exchange books all Drift yield into the
+    /// vault value and mints nothing for it (a single 10,000 bps pool cut),
+    /// so the accrued USDC adds one for one.
+    pub fn protocol_tvl_at(&self, unix_timestamp: i64) -> u64 {
+        let spot_market = &self.spot_market;
+        let interest = spot_market.cumulative_deposit_interest;
+        let projected =
+            spot_market.projected_cumulative_deposit_interest(unix_timestamp);
+        if projected <= interest {
+            return self.protocol_tvl;
+        }
+
+        let scaled_balance = self.drift_scaled_balance as u128;
+        let accrued = spot_market
+            .deposit_token_amount_at(scaled_balance, projected)
+            - spot_market.deposit_token_amount_at(scaled_balance, interest);
+        self.protocol_tvl
+            .saturating_add(accrued.min(u64::MAX as u128) as u64)
+    }
+
+    /// USDC per receipt token at the clock's time, from the same TVL and
+    /// supply quotes use. One before the first deposit.
+    pub fn price_per_share(&self) -> Decimal {
+        self.price_per_share_at(self.clock.unix_timestamp())
+    }
+
+    /// USDC per receipt token at `unix_timestamp`, valued like
+    /// `price_per_share`.
+    pub fn price_per_share_at(&self, unix_timestamp: i64) -> Decimal {
+        state::exchange_rate(
+            self.protocol_tvl_at(unix_timestamp),
+            self.effective_supply,
+        )
+    }
+
+    /// Token-2022 transfer fee the receipt mint charges this epoch.
+    fn receipt_transfer_fee(&self) -> Option<&TransferFee> {
+        self.receipt_mint_extensions
+            .transfer_fee(self.clock.epoch())
+    }
+
+    /// Receipt tokens left after a transfer of `amount`.
+    fn receipt_after_transfer_fee(&self, amount: u64) -> u64 {
+        self.receipt_transfer_fee()
+            .map_or(amount, |fee| amount - fee.fee_on(amount))
+    }
+
+    /// Receipt tokens to transfer so that `amount` arrives.
+    fn receipt_before_transfer_fee(&self, amount: u64) -> error::Result<u64> {
+        match self.receipt_transfer_fee() {
+            Some(fee) => fee
+                .pre_fee_amount(amount)
+                .ok_or(ReflectAmmError::MathOverflow),
+            None => Ok(amount),
+        }
+    }
+
+    /// Limits from the Drift spot market and the USDC the controller holds,
+    /// at the given TVL.
+    fn compute_capacity(
+        spot_market: &DriftSpotMarket,
+        protocol_tvl: u64,
+        idle_usdc: u64,
+    ) -> Capacity {
+        Capacity {
+            max_deposit: spot_market.remaining_deposit_capacity(),
+            max_redeem: idle_usdc
+                .saturating_add(spot_market.withdrawable_amount())
+                .min(protocol_tvl),
+        }
+    }
+
+    /// Accounts `update` has never received. Quotes need all of them.
+    pub fn missing_accounts(&self) -> Vec<Pubkey> {
+        self.get_accounts_to_update()
+            .into_iter()
+            .filter(|pubkey| !self.account_cache.contains_key(pubkey))
+            .collect()
+    }
+
+    /// `update` with the slot each account was observed at, for sources
+    /// that know it, such as account subscriptions. Accounts without one
+    /// count as observed at the clock's slot, as with `update`.
+    pub fn update_observed(
+        &mut self,
+        account_map: &AccountMap,
+        observed_slots: &HashMap<Pubkey, u64>,
+    ) -> anyhow::Result<()> {
+        Ok(self.try_update(account_map, observed_slots)?)
+    }
+
+    /// Decodes the accounts of `account_map` that changed and recomputes
+    /// from them and what is cached for the rest. Accounts are taken one by
+    /// one: one that does not decode is dropped and reported, while the
+    /// rest of its batch still applies. Fails while an account has never
+    /// been received, keeping what arrived so far. A batch with nothing
+    /// usable leaves the state and its stamp alone.
+    fn try_update(
+        &mut self,
+        account_map: &AccountMap,
+        observed_slots: &HashMap<Pubkey, u64>,
+    ) -> error::Result<()> {
+        let mut decoded = self.decoded.clone();
+        let mut changed = Vec::new();
+        let mut unchanged = Vec::new();
+        let mut error = None;
+        for pubkey in self.get_accounts_to_update() {
+            let Some(account) = account_map.get(&pubkey) else {
+                continue;
+            };
+            // Same data as cached: nothing to decode.
+            if self.account_cache.get(&pubkey) == Some(account) {
+                unchanged.push(pubkey);
+                continue;
+            }
+            match self.decode_account(&pubkey, account, &mut decoded) {
+                Ok(()) => changed.push(pubkey),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        let missing =
+            self.get_accounts_to_update().into_iter().find(|pubkey| {
+                !changed.contains(pubkey)
+                    && !self.account_cache.contains_key(pubkey)
+            });
+        if let Some(pubkey) = missing {
+            self.cache_accounts(account_map, &changed, decoded);
+            self.observe(&changed, &unchanged, observed_slots);
+            return Err(
+                error.unwrap_or(ReflectAmmError::MissingAccount(pubkey))
+            );
+        }
+
+        // usdc-plus-exchange reads these four together, so the exchange
+        // components only move when one of them does.
+        let exchange_inputs = [
             self.usdc_plus_controller,
+            self.drift_usdc_spot_market,
             self.usdc_plus_drift_user_acc,
             self.usdc_plus_mint,
-            self.drift_usdc_spot_market,
-        ]
+        ];
+        let exchange_components = if self.state.is_none()
+            || changed
+                .iter()
+                .any(|pubkey| exchange_inputs.contains(pubkey))
+        {
+            let [controller, spot_market, user, mint] =
+                exchange_inputs.map(|pubkey| {
+                    changed
+                        .contains(&pubkey)
+                        .then(|| account_map.get(&pubkey))
+                        .flatten()
+                        .or_else(|| self.account_cache.get(&pubkey))
+                        .map_or(&[][..], |account| account.data.as_slice())
+                });
+            usdc_plus_exchange::get_exchange_components(
+                controller,
+                spot_market,
+                user,
+                mint,
+            )
+            .map_err(|e| ReflectAmmError::ExchangeComponents(e.to_string()))
+        } else {
+            Ok((self.protocol_tvl, self.effective_supply))
+        };
+        let exchange_components = match exchange_components {
+            Ok(exchange_components) => Some(exchange_components),
+            Err(e) => {
+                // The new inputs cannot be valued together with the rest:
+                // keep the ones the current components come from.
+                changed.retain(|pubkey| !exchange_inputs.contains(pubkey));
+                decoded.controller = self.decoded.controller.clone();
+                decoded.spot_market = self.decoded.spot_market;
+                decoded.drift_position = self.decoded.drift_position;
+                decoded.receipt_mint = self.decoded.receipt_mint;
+                error.get_or_insert(e);
+                self.state
+                    .is_some()
+                    .then_some((self.protocol_tvl, self.effective_supply))
+            }
+        };
+
+        if changed.is_empty() && unchanged.is_empty() {
+            return error.map_or(Ok(()), Err);
+        }
+        self.cache_accounts(account_map, &changed, decoded);
+        self.observe(&changed, &unchanged, observed_slots);
+        if let Some((protocol_tvl, supply)) = exchange_components {
+            self.apply_decoded(protocol_tvl, supply);
+        }
+        error.map_or(Ok(()), Err)
     }
 
-    fn update(&mut self, account_map: &AccountMap) -> anyhow::Result<()> {
-        // Get
-        let usdc_plus_mint =
-            try_get_account_data(account_map, &self.usdc_plus_mint)?;
-        let usdc_plus_drift_user_acc =
-            try_get_account_data(account_map, &self.usdc_plus_drift_user_acc)?;
-        let drift_usdc_spot_market =
-            try_get_account_data(account_map, &self.drift_usdc_spot_market)?;
-        let usdc_plus_controller =
-            try_get_account_data(account_map, &self.usdc_plus_controller)?;
-
-        let (protocol_tvl, supply) =
-            usdc_plus_exchange::get_exchange_components(
-                usdc_plus_controller,
-                drift_usdc_spot_market,
-                usdc_plus_drift_user_acc,
-                usdc_plus_mint,
-            )?;
+    fn cache_accounts(
+        &mut self,
+        account_map: &AccountMap,
+        changed: &[Pubkey],
+        decoded: DecodedAccounts,
+    ) {
+        for pubkey in changed {
+            self.account_cache
+                .insert(*pubkey, account_map[pubkey].clone());
+        }
+        self.decoded = decoded;
+    }
 
-        self.protocol_tvl = protocol_tvl;
-        self.effective_supply = supply;
+    /// Records the slot each accepted account was observed at, the clock's
+    /// slot unless `observed_slots` has it, and the clock's time.
+    fn observe(
+        &mut self,
+        changed: &[Pubkey],
+        unchanged: &[Pubkey],
+        observed_slots: &HashMap<Pubkey, u64>,
+    ) {
+        for pubkey in changed.iter().chain(unchanged) {
+            let slot = observed_slots
+                .get(pubkey)
+                .copied()
+                .unwrap_or_else(|| self.clock.slot());
+            self.account_slots.insert(*pubkey, slot);
+            self.account_times
+                .insert(*pubkey, self.clock.unix_timestamp());
+        }
+    }
 
+    /// Slot and time of the accounts observed longest ago, which bound how
+    /// current the state is. `None` while an account has never been
+    /// received.
+    fn oldest_observation(&self) -> Option<(u64, i64)> {
+        let pubkeys = self.get_accounts_to_update();
+        let slot = pubkeys
+            .iter()
+            .map(|pubkey| self.account_slots.get(pubkey).copied())
+            .min()
+            .flatten()?;
+        let unix_timestamp = pubkeys
+            .iter()
+            .map(|pubkey| self.account_times.get(pubkey).copied())
+            .min()
+            .flatten()?;
+        Some((slot, unix_timestamp))
+    }
+
+    /// Decodes `account` into the field of `decoded` it belongs to.
+    fn decode_account(
+        &self,
+        pubkey: &Pubkey,
+        account: &Account,
+        decoded: &mut DecodedAccounts,
+    ) -> error::Result<()> {
+        let data = account.data.as_slice();
+        let invalid = |e| ReflectAmmError::invalid_account(*pubkey, e);
+        if *pubkey == self.usdc_plus_controller {
+            decoded.controller = Some(
+                DriftUsdcController::try_deserialize(data).map_err(invalid)?,
+            );
+        } else if *pubkey == self.usdc_plus_drift_user_acc {
+            decoded.drift_position = Some(self.decode_drift_position(data)?);
+        } else if *pubkey == self.usdc_plus_mint {
+            decoded.receipt_mint = Some(ReceiptMint {
+                token_program: token::token_program_of(&account.owner)
+                    .map_err(invalid)?,
+                extensions: MintExtensions::try_deserialize(data)
+                    .map_err(invalid)?,
+                supply: token::mint_supply(data).map_err(invalid)?,
+            });
+        } else if *pubkey == self.drift_usdc_spot_market {
+            let spot_market =
+                DriftSpotMarket::try_deserialize(data).map_err(invalid)?;
+            if spot_market.market_index != USDC_SPOT_MARKET_INDEX {
+                return Err(ReflectAmmError::invalid_account(
+                    *pubkey,
+                    format!("is spot market {}", spot_market.market_index),
+                ));
+            }
+            if spot_market.oracle != self.usdc_oracle {
+                return Err(ReflectAmmError::invalid_account(
+                    *pubkey,
+                    format!("priced by oracle {}", spot_market.oracle),
+                ));
+            }
+            decoded.spot_market = Some(spot_market);
+        } else if *pubkey == self.controller_usdc_ata {
+            decoded.idle_usdc =
+                Some(token::token_account_amount(data).map_err(invalid)?);
+        } else if *pubkey == self.usdc_oracle {
+            decoded.oracle_price =
+                Some(OraclePrice::try_deserialize(data).map_err(invalid)?);
+        }
         Ok(())
     }
 
-    fn quote(&self, quote_params: &QuoteParams) -> anyhow::Result<Quote> {
-        let (in_amount, out_amount) = match quote_params.swap_mode {
-            SwapMode::ExactIn => {
-                let out = if quote_params.input_mint == usdc_mint::ID {
-                    usdc_plus_exchange::compute_tokens_from_usdc(
-                        quote_params.amount,
-                        self.protocol_tvl,
-                        self.effective_supply,
-                    )?
-                } else {
-                    usdc_plus_exchange::compute_usdc_from_tokens(
-                        quote_params.amount,
-                        self.protocol_tvl,
-                        self.effective_supply,
-                    )?
-                };
-                (quote_params.amount, out)
+    /// The strategy's USDC deposit. usdc-plus-exchange values the strategy
+    /// from the user's first spot position, so the deposit has to sit there.
+    fn decode_drift_position(
+        &self,
+        data: &[u8],
+    ) -> error::Result<UserSpotPosition> {
+        let invalid = |e: String| {
+            ReflectAmmError::invalid_account(self.usdc_plus_drift_user_acc, e)
+        };
+        match find_user_spot_position(data, USDC_SPOT_MARKET_INDEX) {
+            Ok(Some((0, position))) if !position.is_borrow => Ok(position),
+            Ok(Some((index, position))) if !position.is_borrow => Err(invalid(
+                format!("USDC position in slot {index}, expected slot 0"),
+            )),
+            Ok(Some(_)) => Err(invalid("USDC position is a borrow".to_owned())),
+            Ok(None) => Err(invalid("no USDC spot position".to_owned())),
+            Err(e) => Err(invalid(e.to_string())),
+        }
+    }
+
+    /// Replaces the state quotes read with what `decoded` holds, once every
+    /// account has decoded.
+    fn apply_decoded(&mut self, protocol_tvl: u64, supply: u64) {
+        let DecodedAccounts {
+            controller: Some(controller),
+            drift_position: Some(position),
+            receipt_mint: Some(receipt_mint),
+            spot_market: Some(spot_market),
+            idle_usdc: Some(idle_usdc),
+            oracle_price: Some(oracle_price),
+        } = self.decoded.clone()
+        else {
+            return;
+        };
+
+        self.protocol_tvl = protocol_tvl;
+        self.effective_supply = supply;
+        self.drift_scaled_balance = position.scaled_balance;
+        self.capacity =
+            Self::compute_capacity(&spot_market, protocol_tvl, idle_usdc);
+        let (slot, unix_timestamp) =
+            self.oldest_observation().unwrap_or_default();
+        self.state = Some(ReflectState {
+            slot,
+            controller_key: self.usdc_plus_controller,
+            controller,
+            receipt_mint_supply: receipt_mint.supply,
+            drift_deposit_balance: clamp_u64(
+                position.deposit_token_amount(&spot_market),
+            ),
+            drift_borrow_balance: clamp_u64(
+                position.borrow_token_amount(&spot_market),
+            ),
+            cumulative_deposit_interest: spot_market
+                .cumulative_deposit_interest,
+            cumulative_borrow_interest: spot_market.cumulative_borrow_interest,
+            protocol_tvl,
+            effective_supply: supply,
+            exchange_rate: state::exchange_rate(protocol_tvl, supply),
+        });
+        self.receipt_token_program = receipt_mint.token_program;
+        self.receipt_mint_extensions = receipt_mint.extensions;
+        self.spot_market = spot_market;
+        self.oracle_price = Some(oracle_price);
+        self.last_update = Some(UpdateStamp {
+            slot,
+            unix_timestamp,
+            spot_market_last_interest_ts: spot_market.last_interest_ts,
+        });
+    }
+
+    fn try_quote(&self, quote_params: &QuoteParams) -> error::Result<Quote> {
+        let is_deposit = self.validate_mint_pair(
+            &quote_params.input_mint,
+            &quote_params.output_mint,
+        )?;
+        let Some(last_update) = self.last_update else {
+            return Err(ReflectAmmError::NotUpdated(self.usdc_plus_controller));
+        };
+        if let Some(max_slots) = self.max_staleness_slots {
+            let age = last_update.age(&self.clock);
+            if age.slots > max_slots {
+                return Err(ReflectAmmError::StaleState {
+                    age_slots: age.slots,
+                    max_slots,
+                });
             }
-            SwapMode::ExactOut => {
-                let inp = if quote_params.input_mint == usdc_mint::ID {
-                    // Inverse: in = out * effective_tvl / supply.
-                    usdc_plus_exchange::compute_usdc_from_tokens(
-                        quote_params.amount,
-                        self.protocol_tvl,
+        }
+        if let Some(violation) = self.oracle_violation() {
+            return Err(violation.into());
+        }
+        let protocol_tvl = self.projected_protocol_tvl();
+
+        // No Reflect fee is modelled: the controller's fee configuration has
+        // no confirmed layout yet. A Token-2022 transfer fee on the receipt
+        // mint is withheld from receipt tokens moving between the user and
+        // the controller.
+        let amount = quote_params.amount;
+        let (in_amount, out_amount) = match (quote_params.swap_mode, is_deposit)
+        {
+            (SwapMode::ExactIn, true) => (
+                amount,
+                self.receipt_after_transfer_fee(
+                    exchange::compute_tokens_from_usdc(
+                        amount,
+                        protocol_tvl,
                         self.effective_supply,
-                    )?
-                } else {
-                    // Inverse: in = out * supply / effective_tvl.
-                    usdc_plus_exchange::compute_tokens_from_usdc(
-                        quote_params.amount,
-                        self.protocol_tvl,
+                    )?,
+                ),
+            ),
+            (SwapMode::ExactIn, false) => (
+                amount,
+                exchange::compute_usdc_from_tokens(
+                    self.receipt_after_transfer_fee(amount),
+                    protocol_tvl,
+                    self.effective_supply,
+                )?,
+            ),
+            (SwapMode::ExactOut, true) => (
+                exchange::compute_usdc_for_tokens_out(
+                    self.receipt_before_transfer_fee(amount)?,
+                    protocol_tvl,
+                    self.effective_supply,
+                )?,
+                amount,
+            ),
+            (SwapMode::ExactOut, false) => (
+                self.receipt_before_transfer_fee(
+                    exchange::compute_tokens_for_usdc_out(
+                        amount,
+                        protocol_tvl,
                         self.effective_supply,
-                    )?
-                };
-                (inp, quote_params.amount)
-            }
+                    )?,
+                )?,
+                amount,
+            ),
         };
 
+        // USDC crossing the controller.
+        let usdc_amount = if is_deposit { in_amount } else { out_amount };
+        self.capacity.check(is_deposit, usdc_amount)?;
+
         Ok(Quote {
             in_amount,
             out_amount,
-            fee_amount: 0,
-            fee_mint: quote_params.input_mint,
+            fee_mint: self.usdc_mint,
             ..Quote::default()
         })
     }
 
-    fn get_swap_and_account_metas(
+    /// Checks that the pair is USDC and this strategy's receipt token, in
+    /// either order. Returns whether the swap is a deposit.
+    fn validate_mint_pair(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> error::Result<bool> {
+        if *input_mint == self.usdc_mint && *output_mint == self.usdc_plus_mint
+        {
+            Ok(true)
+        } else if *input_mint == self.usdc_plus_mint
+            && *output_mint == self.usdc_mint
+        {
+            Ok(false)
+        } else {
+            Err(ReflectAmmError::InvalidMintPair {
+                input: *input_mint,
+                output: *output_mint,
+            })
+        }
+    }
+
+    /// Validates the mint pair and builds the Reflect accounts for a swap.
+    fn build_swap(
         &self,
         swap_params: &SwapParams,
-    ) -> anyhow::Result<SwapAndAccountMetas> {
+    ) -> error::Result<(ReflectSwapVariant, Vec<AccountMeta>)> {
         let SwapParams {
             source_mint,
             destination_mint,
@@ -198,53 +823,293 @@ impl Amm for ReflectAmm {
             ..
         } = swap_params;
 
-        // Validate mint pair
-        let valid_pair = (*source_mint == usdc_mint::ID
-            && *destination_mint == usdc_plus_mint::ID)
-            || (*source_mint == usdc_plus_mint::ID
-                && *destination_mint == usdc_mint::ID);
-
-        if !valid_pair {
-            return Err(anyhow!(
-                "Invalid mint pair: source {} destination {}",
-                source_mint,
-                destination_mint
-            ));
-        }
-
-        let is_deposit = *source_mint == usdc_mint::ID;
+        let is_deposit =
+            self.validate_mint_pair(source_mint, destination_mint)?;
 
         let (user_usdc_ata, user_receipt_ata) = if is_deposit {
             (*source_token_account, *destination_token_account)
         } else {
             (*destination_token_account, *source_token_account)
         };
+        if self.check_user_token_accounts {
+            let expected = self.user_token_accounts(token_transfer_authority);
+            for (expected, actual) in [
+                (expected.usdc, user_usdc_ata),
+                (expected.receipt, user_receipt_ata),
+            ] {
+                if expected != actual {
+                    return Err(ReflectAmmError::UnexpectedTokenAccount {
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
 
-        Ok(SwapAndAccountMetas {
-            swap: Swap::TokenSwap, // Placeholder, should be ReflectS1
-            account_metas: ReflectSwap {
-                user: *token_transfer_authority,
-                user_receipt_ata,
+        Ok((
+            ReflectSwapVariant::from_direction(is_deposit),
+            self.reflect_swap(
+                *token_transfer_authority,
                 user_usdc_ata,
-                main: self.main,
-                usdc_controller: self.usdc_plus_controller,
-                admin_permissions: self.admin_permissions,
-                controller_usdc_ata: self.controller_usdc_ata,
-                receipt_mint: self.usdc_plus_mint,
-                drift_program: self.drift_program,
-                drift_state: self.drift_state,
-                drift_user_stats: self.drift_user_stats,
-                referrer_user_stats: self.referrer_user_stats,
-                referrer_user: self.referrer_user,
-                drift_user_account: self.usdc_plus_drift_user_acc,
-                drift_spot_market_vault: self.drift_spot_market_vault,
-                drift_vault: self.drift_vault,
-                usdc_oracle: self.usdc_oracle,
-                drift_usdc_spot_market: self.drift_usdc_spot_market,
-            }
+                user_receipt_ata,
+            )
             .try_into()?,
+        ))
+    }
+
+    /// Associated token accounts of `user`. USDC is an SPL Token mint; the
+    /// receipt mint uses the program found by `update`.
+    pub fn user_token_accounts(&self, user: &Pubkey) -> UserTokenAccounts {
+        UserTokenAccounts::derive(
+            user,
+            &self.usdc_mint,
+            &token_program::ID,
+            &self.usdc_plus_mint,
+            &self.receipt_token_program,
+        )
+    }
+
+    /// Checks that `account`, a token account a swap of `user` moves, holds
+    /// USDC or the receipt token, belongs to `user` and is owned by that
+    /// mint's token program. Swap params carry only addresses, so callers
+    /// that fetch the user's accounts check them here.
+    pub fn check_user_token_account(
+        &self,
+        user: &Pubkey,
+        pubkey: &Pubkey,
+        account: &Account,
+    ) -> error::Result<()> {
+        let invalid = |e: String| ReflectAmmError::invalid_account(*pubkey, e);
+        let (mint, owner) = token::token_account_keys(&account.data)
+            .map_err(|e| invalid(e.to_string()))?;
+        let token_program = if mint == self.usdc_mint {
+            token_program::ID
+        } else if mint == self.usdc_plus_mint {
+            self.receipt_token_program
+        } else {
+            return Err(invalid(format!("holds mint {mint}")));
+        };
+        if account.owner != token_program {
+            return Err(invalid(format!(
+                "owned by {}, but {mint} is a {token_program} mint",
+                account.owner
+            )));
+        }
+        if owner != *user {
+            return Err(invalid(format!("belongs to {owner}, not {user}")));
+        }
+        Ok(())
+    }
+
+    /// Idempotently creates the ATA a swap pays out to: the receipt token
+    /// account when minting, the USDC account when redeeming. Prepend it for
+    /// users who may not have one yet.
+    pub fn create_destination_token_account_instruction(
+        &self,
+        payer: &Pubkey,
+        user: &Pubkey,
+        is_deposit: bool,
+    ) -> Instruction {
+        let (mint, token_program) = if is_deposit {
+            (&self.usdc_plus_mint, &self.receipt_token_program)
+        } else {
+            (&self.usdc_mint, &token_program::ID)
+        };
+        ata::create_associated_token_account_idempotent(
+            payer,
+            user,
+            mint,
+            token_program,
+        )
+    }
+
+    /// Standalone Reflect instruction minting receipt tokens for `amount`
+    /// USDC, failing on-chain below `min_out`. `user` signs and owns both
+    /// token accounts.
+    pub fn build_mint_instruction(
+        &self,
+        user: Pubkey,
+        user_usdc_ata: Pubkey,
+        user_receipt_ata: Pubkey,
+        amount: u64,
+        min_out: u64,
+    ) -> error::Result<Instruction> {
+        self.build_instruction(
+            ReflectSwapVariant::Mint,
+            self.reflect_swap(user, user_usdc_ata, user_receipt_ata),
+            amount,
+            min_out,
+        )
+    }
+
+    /// Standalone Reflect instruction redeeming `amount` receipt tokens,
+    /// failing on-chain below `min_out` USDC.
+    pub fn build_redeem_instruction(
+        &self,
+        user: Pubkey,
+        user_usdc_ata: Pubkey,
+        user_receipt_ata: Pubkey,
+        amount: u64,
+        min_out: u64,
+    ) -> error::Result<Instruction> {
+        self.build_instruction(
+            ReflectSwapVariant::Redeem,
+            self.reflect_swap(user, user_usdc_ata, user_receipt_ata),
+            amount,
+            min_out,
+        )
+    }
+
+    fn build_instruction(
+        &self,
+        variant: ReflectSwapVariant,
+        swap: ReflectSwap,
+        amount: u64,
+        min_out: u64,
+    ) -> error::Result<Instruction> {
+        Ok(Instruction {
+            program_id: self.program_id,
+            accounts: swap.try_into()?,
+            data: variant.instruction_data(amount, min_out),
+        })
+    }
+
+    /// Accounts of a Reflect mint or redeem, in the program's order for both
+    /// router and direct calls.
+    fn reflect_swap(
+        &self,
+        user: Pubkey,
+        user_usdc_ata: Pubkey,
+        user_receipt_ata: Pubkey,
+    ) -> ReflectSwap {
+        ReflectSwap {
+            user,
+            user_receipt_ata,
+            user_usdc_ata,
+            main: self.main,
+            usdc_controller: self.usdc_plus_controller,
+            admin_permissions: self.admin_permissions,
+            controller_usdc_ata: self.controller_usdc_ata,
+            receipt_mint: self.usdc_plus_mint,
+            token_program: token_program::ID,
+            receipt_token_program: self.receipt_token_program,
+            drift_program: self.drift_program,
+            drift_state: self.drift_state,
+            drift_user_stats: self.drift_user_stats,
+            referrer_user_stats: self.referrer_user_stats,
+            referrer_user: self.referrer_user,
+            drift_user_account: self.usdc_plus_drift_user_acc,
+            drift_spot_market_vault: self.drift_spot_market_vault,
+            drift_vault: self.drift_vault,
+            usdc_oracle: self.usdc_oracle,
+            drift_usdc_spot_market: self.drift_usdc_spot_market,
+        }
+    }
+
+    /// Swap and accounts for routers without a Reflect `Swap` variant.
+    #[cfg(feature = "custom-swap")]
+    pub fn get_reflect_swap_and_account_metas(
+        &self,
+        swap_params: &SwapParams,
+    ) -> anyhow::Result<ReflectSwapAndAccountMetas> {
+        let (swap, account_metas) = self.build_swap(swap_params)?;
+        Ok(ReflectSwapAndAccountMetas {
+            swap,
+            account_metas,
         })
     }
+}
+
+impl Amm for ReflectAmm {
+    fn from_keyed_account(
+        keyed_account: &KeyedAccount,
+        amm_context: &AmmContext,
+    ) -> anyhow::Result<Self> {
+        let config = ReflectConfig::from_params(keyed_account.params.as_ref())
+            .map_err(|e| {
+                ReflectAmmError::invalid_account(
+                    keyed_account.key,
+                    format!("{e:#}"),
+                )
+            })?;
+        if keyed_account.account.owner != config.reflect_program {
+            return Err(ReflectAmmError::invalid_account(
+                keyed_account.key,
+                "not owned by the Reflect program",
+            )
+            .into());
+        }
+
+        let controller =
+            DriftUsdcController::try_deserialize(&keyed_account.account.data)
+                .map_err(|e| {
+                ReflectAmmError::invalid_account(keyed_account.key, e)
+            })?;
+
+        Ok(ReflectAmm::from_controller(
+            &config,
+            keyed_account.key,
+            &controller,
+            amm_context.clock_ref.clone(),
+        ))
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    /// ID of reflect delta neutral.
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    // This signs to drift and holds tokens.
+    fn key(&self) -> Pubkey {
+        self.usdc_plus_controller
+    }
+
+    /// Mints between which you can exhcange.
+    fn get_reserve_mints(&self) -> Vec<Pubkey> {
+        vec![self.usdc_mint, self.usdc_plus_mint]
+    }
+
+    /// Accounts needed to generate a quote.
+    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
+        // Whatver the exchnage library needs for exchange with drift only.
+        vec![
+            self.usdc_plus_controller,
+            self.usdc_plus_drift_user_acc,
+            self.usdc_plus_mint,
+            self.drift_usdc_spot_market,
+            self.controller_usdc_ata,
+            self.usdc_oracle,
+        ]
+    }
+
+    fn update(&mut self, account_map: &AccountMap) -> anyhow::Result<()> {
+        Ok(self.try_update(account_map, &HashMap::new())?)
+    }
+
+    fn quote(&self, quote_params: &QuoteParams) -> anyhow::Result<Quote> {
+        Ok(self.try_quote(quote_params)?)
+    }
+
+    /// Always fails, with or without the `custom-swap` feature:
+    /// jupiter-amm-interface 0.6 has no Reflect `Swap` variant, and any other
+    /// variant would make the router encode another DEX's CPI. A router that
+    /// holds its markets as `dyn Amm` keeps the `ReflectAmm`s too, keyed by
+    /// `Amm::key`, and for a Reflect hop calls
+    /// `get_reflect_swap_and_account_metas` (feature `custom-swap`). It
+    /// invokes `Amm::program_id` with those accounts and
+    /// `ReflectSwapVariant::instruction_data`.
+    fn get_swap_and_account_metas(
+        &self,
+        swap_params: &SwapParams,
+    ) -> anyhow::Result<SwapAndAccountMetas> {
+        // Validate before refusing so callers still see bad pairs first.
+        self.build_swap(swap_params)?;
+        Err(ReflectAmmError::UnsupportedSwapVariant.into())
+    }
 
     fn clone_amm(&self) -> Box<dyn Amm + Send + Sync> {
         Box::new(self.clone())
@@ -253,65 +1118,188 @@ impl Amm for ReflectAmm {
     fn supports_exact_out(&self) -> bool {
         true
     }
+
+    /// USDC is off its peg or priced too uncertainly for Drift to take
+    /// deposits, or some account has never been received. Pauses are not
+    /// read: neither the controller nor `admin_permissions` has a confirmed
+    /// layout for them yet.
+    fn is_active(&self) -> bool {
+        self.oracle_violation().is_none() && self.missing_accounts().is_empty()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use solana_client::rpc_client::RpcClient;
+    use std::sync::atomic::Ordering;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rust_decimal::Decimal;
     use solana_sdk::pubkey::Pubkey;
 
     use super::*;
+    use crate::fixtures::{
+        usdc_plus_account_map, usdc_plus_amm,
+        usdc_plus_local::{
+            CONTROLLER, CONTROLLER_USDC_ATA, DRIFT_USER, RECEIPT_MINT,
+        },
+    };
+
+    // Exchange components of the USDC+ fixture.
+    const PROTOCOL_TVL: u64 = 77_031_500_611;
+    const EFFECTIVE_SUPPLY: u64 = 75_986_842_576;
+
+    fn updated_amm() -> ReflectAmm {
+        let mut amm = usdc_plus_amm();
+        amm.update(&usdc_plus_account_map()).unwrap();
+        amm
+    }
 
-    const RPC_URL: &str = "https://api.mainnet-beta.solana.com";
-
-    fn create_account_map(rpc: &RpcClient, pubkeys: &[Pubkey]) -> AccountMap {
-        let accounts = rpc.get_multiple_accounts(pubkeys).unwrap();
-        let mut map = AccountMap::default();
-        for (i, account) in accounts.into_iter().enumerate() {
-            if let Some(acc) = account {
-                map.insert(pubkeys[i], acc);
-            }
+    /// The recorded USDC+ controller, on the local cluster it comes from.
+    fn recorded_controller() -> KeyedAccount {
+        KeyedAccount {
+            key: CONTROLLER,
+            account: usdc_plus_account_map().remove(&CONTROLLER).unwrap(),
+            params: Some(serde_json::json!({ "cluster": "localnet" })),
         }
-        map
+    }
+
+    #[test]
+    fn test_reflect_amm_from_keyed_account() {
+        let keyed_account = recorded_controller();
+        assert_eq!(keyed_account.account.owner, reflect::ID);
+        let mut amm = ReflectAmm::from_keyed_account(
+            &keyed_account,
+            &AmmContext {
+                clock_ref: Default::default(),
+            },
+        )
+        .unwrap();
+
+        // The receipt mint comes from the controller's data, the Drift
+        // accounts from its key: all of them are the recorded ones.
+        assert_eq!(amm.usdc_plus_controller, CONTROLLER);
+        assert_eq!(amm.usdc_plus_mint, RECEIPT_MINT);
+        assert_eq!(amm.usdc_plus_drift_user_acc, DRIFT_USER);
+        assert_eq!(amm.controller_usdc_ata, CONTROLLER_USDC_ATA);
+        assert_eq!(amm.drift_usdc_spot_market, usdc_spot_market::ID);
+        assert_eq!(amm.usdc_oracle, usdc_oracle::ID);
+        amm.update(&usdc_plus_account_map()).unwrap();
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+        assert_eq!(amm.effective_supply, EFFECTIVE_SUPPLY);
+    }
+
+    #[test]
+    fn test_reflect_amm_from_keyed_account_params() {
+        let amm_context = AmmContext {
+            clock_ref: Default::default(),
+        };
+        let mut keyed_account = recorded_controller();
+
+        let main = Pubkey::new_unique();
+        let drift_program = Pubkey::new_unique();
+        keyed_account.params = Some(serde_json::json!({
+            "cluster": "localnet",
+            "main": main.to_string(),
+            "drift_program": drift_program.to_string(),
+        }));
+        let amm = ReflectAmm::from_keyed_account(&keyed_account, &amm_context)
+            .unwrap();
+        assert_eq!(amm.main, main);
+        assert_eq!(amm.drift_program, drift_program);
+        assert_eq!(amm.drift_state, pda::find_drift_state(&drift_program));
+        assert_eq!(
+            amm.usdc_plus_drift_user_acc,
+            pda::find_drift_user(&drift_program, &CONTROLLER, 0)
+        );
+
+        // Devnet has no Reflect or referrer accounts to fall back on.
+        keyed_account.params = Some(serde_json::json!({ "cluster": "devnet" }));
+        assert!(ReflectAmm::from_keyed_account(&keyed_account, &amm_context)
+            .is_err());
+    }
+
+    #[test]
+    fn test_reflect_amm_from_keyed_account_not_controller() {
+        let amm_context = AmmContext {
+            clock_ref: Default::default(),
+        };
+
+        let mut wrong_owner = recorded_controller();
+        wrong_owner.account.owner = Pubkey::new_unique();
+        assert!(
+            ReflectAmm::from_keyed_account(&wrong_owner, &amm_context).is_err(),
+            "Should fail when the account is not owned by Reflect"
+        );
+
+        let mut wrong_discriminator = recorded_controller();
+        wrong_discriminator.account.data[..8].fill(0);
+        assert!(
+            ReflectAmm::from_keyed_account(&wrong_discriminator, &amm_context)
+                .is_err(),
+            "Should fail when the account is not a controller"
+        );
     }
 
     #[test]
     fn test_reflect_amm_accounts_to_update() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
         let accounts = amm.get_accounts_to_update();
 
-        assert_eq!(accounts.len(), 4);
+        assert_eq!(accounts.len(), 6);
         assert!(accounts.contains(&amm.usdc_plus_controller));
         assert!(accounts.contains(&amm.usdc_plus_drift_user_acc));
         assert!(accounts.contains(&amm.usdc_plus_mint));
         assert!(accounts.contains(&amm.drift_usdc_spot_market));
+        assert!(accounts.contains(&amm.controller_usdc_ata));
+        assert!(accounts.contains(&amm.usdc_oracle));
+    }
+
+    #[test]
+    fn test_reflect_amm_fixture_covers_accounts_to_update() {
+        let account_map = usdc_plus_account_map();
+        for pubkey in usdc_plus_amm().get_accounts_to_update() {
+            assert!(account_map.contains_key(&pubkey), "Missing {pubkey}");
+        }
     }
 
     #[test]
     fn test_reflect_amm_update_and_quote() {
-        let rpc = RpcClient::new(RPC_URL);
-        let mut amm = ReflectAmm::new();
+        let amm = updated_amm();
 
-        // Fetch accounts needed for the update.
-        let accounts_to_update = amm.get_accounts_to_update();
-        let account_map = create_account_map(&rpc, &accounts_to_update);
+        // Verify variables were set.
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+        assert_eq!(amm.effective_supply, EFFECTIVE_SUPPLY);
+    }
 
-        // Update AMM state.
-        amm.update(&account_map).unwrap();
+    #[test]
+    fn test_reflect_amm_state_snapshot() {
+        assert_eq!(usdc_plus_amm().state, None);
+
+        let amm = updated_amm();
+        let state = amm.state.clone().unwrap();
+        assert_eq!(state.controller_key, amm.usdc_plus_controller);
+        assert_eq!(state.protocol_tvl, PROTOCOL_TVL);
+        assert_eq!(state.effective_supply, EFFECTIVE_SUPPLY);
+        assert_eq!(state.drift_deposit_balance, PROTOCOL_TVL);
+        assert_eq!(state.drift_borrow_balance, 0);
+        assert_eq!(
+            state.cumulative_deposit_interest,
+            amm.spot_market.cumulative_deposit_interest
+        );
+        assert_eq!(
+            state.exchange_rate,
+            Decimal::from(PROTOCOL_TVL) / Decimal::from(EFFECTIVE_SUPPLY)
+        );
 
-        // Verify variables were set.
-        assert!(amm.protocol_tvl > 0, "USDC rate should be set");
-        assert!(amm.effective_supply > 0, "USDC+ rate should be set");
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains(&amm.usdc_plus_controller.to_string()));
+        let decoded: ReflectState = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, state);
     }
 
     #[test]
     fn test_reflect_amm_quote_usdc_to_usdc_plus() {
-        let rpc = RpcClient::new(RPC_URL);
-        let mut amm = ReflectAmm::new();
-
-        let accounts_to_update = amm.get_accounts_to_update();
-        let account_map = create_account_map(&rpc, &accounts_to_update);
-        amm.update(&account_map).unwrap();
+        let amm = updated_amm();
 
         // Quote for 100 USDC (6 decimals)
         let in_amount: u64 = 100_000_000;
@@ -319,7 +1307,7 @@ mod tests {
             .quote(&QuoteParams {
                 amount: in_amount,
                 input_mint: usdc_mint::ID,
-                output_mint: usdc_plus_mint::ID,
+                output_mint: RECEIPT_MINT,
                 swap_mode: SwapMode::ExactIn,
             })
             .unwrap();
@@ -330,26 +1318,21 @@ mod tests {
             quote.out_amount as f64 / 1_000_000.0
         );
 
-        assert!(quote.out_amount > 0, "Output amount should be > 0");
+        assert_eq!(quote.out_amount, 98_643_856);
         assert_eq!(quote.in_amount, in_amount);
         assert_eq!(quote.fee_amount, 0);
     }
 
     #[test]
     fn test_reflect_amm_quote_usdc_plus_to_usdc() {
-        let rpc = RpcClient::new(RPC_URL);
-        let mut amm = ReflectAmm::new();
-
-        let accounts_to_update = amm.get_accounts_to_update();
-        let account_map = create_account_map(&rpc, &accounts_to_update);
-        amm.update(&account_map).unwrap();
+        let amm = updated_amm();
 
         // Quote for 100 USDC+ (6 decimals).
         let in_amount: u64 = 100_000_000;
         let quote = amm
             .quote(&QuoteParams {
                 amount: in_amount,
-                input_mint: usdc_plus_mint::ID,
+                input_mint: RECEIPT_MINT,
                 output_mint: usdc_mint::ID,
                 swap_mode: SwapMode::ExactIn,
             })
@@ -361,19 +1344,14 @@ mod tests {
             quote.out_amount as f64 / 1_000_000.0
         );
 
-        assert!(quote.out_amount > 0, "Output amount should be > 0");
+        assert_eq!(quote.out_amount, 101_374_788);
         assert_eq!(quote.in_amount, in_amount);
         assert_eq!(quote.fee_amount, 0);
     }
 
     #[test]
     fn test_reflect_amm_quote_roundtrip() {
-        let rpc = RpcClient::new(RPC_URL);
-        let mut amm = ReflectAmm::new();
-
-        let accounts_to_update = amm.get_accounts_to_update();
-        let account_map = create_account_map(&rpc, &accounts_to_update);
-        amm.update(&account_map).unwrap();
+        let amm = updated_amm();
 
         // Start with 1000 USDC.
         let initial_usdc: u64 = 1_000_000_000;
@@ -383,7 +1361,7 @@ mod tests {
             .quote(&QuoteParams {
                 amount: initial_usdc,
                 input_mint: usdc_mint::ID,
-                output_mint: usdc_plus_mint::ID,
+                output_mint: RECEIPT_MINT,
                 swap_mode: SwapMode::ExactIn,
             })
             .unwrap();
@@ -392,7 +1370,7 @@ mod tests {
         let quote2 = amm
             .quote(&QuoteParams {
                 amount: quote1.out_amount,
-                input_mint: usdc_plus_mint::ID,
+                input_mint: RECEIPT_MINT,
                 output_mint: usdc_mint::ID,
                 swap_mode: SwapMode::ExactIn,
             })
@@ -405,6 +1383,9 @@ mod tests {
             quote2.out_amount as f64 / 1_000_000.0
         );
 
+        assert_eq!(quote1.out_amount, 986_438_560);
+        assert_eq!(quote2.out_amount, 999_999_999);
+
         // Should get back approximately the same amount (within some tolerance due to rounding).
         let diff = (initial_usdc as i64 - quote2.out_amount as i64).abs();
         let tolerance = initial_usdc / 10000; // 0.01% tolerance
@@ -417,24 +1398,683 @@ mod tests {
         );
     }
 
+    fn quote(
+        amm: &ReflectAmm,
+        amount: u64,
+        is_deposit: bool,
+        swap_mode: SwapMode,
+    ) -> Quote {
+        let (input_mint, output_mint) = if is_deposit {
+            (usdc_mint::ID, RECEIPT_MINT)
+        } else {
+            (RECEIPT_MINT, usdc_mint::ID)
+        };
+        amm.quote(&QuoteParams {
+            amount,
+            input_mint,
+            output_mint,
+            swap_mode,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reflect_amm_exact_out_covers_exact_in() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let base = usdc_plus_amm();
+
+        for _ in 0..10_000 {
+            let protocol_tvl = rng.gen_range(1_000_000..100_000_000_000_000);
+            let amm = ReflectAmm {
+                protocol_tvl,
+                effective_supply: rng
+                    .gen_range(protocol_tvl / 2..=protocol_tvl * 2),
+                last_update: Some(UpdateStamp::default()),
+                ..base.clone()
+            };
+            let is_deposit = rng.gen_bool(0.5);
+            let x = rng.gen_range(1..1_000_000_000_000);
+
+            let exact_out = quote(&amm, x, is_deposit, SwapMode::ExactOut);
+            let exact_in =
+                quote(&amm, exact_out.in_amount, is_deposit, SwapMode::ExactIn);
+            assert!(
+                exact_in.out_amount >= x,
+                "{amm:?} is_deposit={is_deposit}: {x} out needs {} in, \
+                 which only gives {}",
+                exact_out.in_amount,
+                exact_in.out_amount
+            );
+        }
+    }
+
+    #[test]
+    fn test_reflect_amm_update_reads_capacity() {
+        let amm = updated_amm();
+        // Drift can release more than the strategy holds.
+        assert_eq!(amm.capacity.max_redeem, PROTOCOL_TVL);
+        // Room left under the spot market's max_token_deposits.
+        assert_eq!(amm.capacity.max_deposit, 268_210_487_213_657);
+    }
+
+    #[test]
+    fn test_reflect_amm_quote_exceeds_capacity() {
+        let amm = ReflectAmm {
+            capacity: Capacity {
+                max_deposit: 50_000_000,
+                max_redeem: 60_000_000,
+            },
+            ..updated_amm()
+        };
+
+        for (is_deposit, available) in [(true, 50_000_000), (false, 60_000_000)]
+        {
+            let (input_mint, output_mint) = if is_deposit {
+                (usdc_mint::ID, RECEIPT_MINT)
+            } else {
+                (RECEIPT_MINT, usdc_mint::ID)
+            };
+            let err = amm
+                .quote(&QuoteParams {
+                    amount: 100_000_000,
+                    input_mint,
+                    output_mint,
+                    swap_mode: SwapMode::ExactIn,
+                })
+                .unwrap_err();
+            let Some(ReflectAmmError::ExceedsCapacity(err)) =
+                err.downcast_ref::<ReflectAmmError>()
+            else {
+                panic!("Unexpected error: {err}");
+            };
+            assert_eq!(err.is_deposit, is_deposit);
+            assert_eq!(err.available, available);
+
+            // Exactly the bound in USDC goes through.
+            let swap_mode = if is_deposit {
+                SwapMode::ExactIn
+            } else {
+                SwapMode::ExactOut
+            };
+            quote(&amm, available, is_deposit, swap_mode);
+        }
+    }
+
+    fn updated_amm_with_oracle(price: i64, conf: u64) -> ReflectAmm {
+        let mut account_map = usdc_plus_account_map();
+        let data = &mut account_map.get_mut(&usdc_oracle::ID).unwrap().data;
+        data[8..16].copy_from_slice(&price.to_le_bytes());
+        data[40..48].copy_from_slice(&conf.to_le_bytes());
+
+        let mut amm = usdc_plus_amm();
+        amm.update(&account_map).unwrap();
+        amm
+    }
+
+    #[test]
+    fn test_reflect_amm_update_reads_oracle() {
+        let amm = updated_amm();
+        let oracle = amm.oracle_price.unwrap();
+        assert_eq!(oracle.price, 999_875);
+        assert_eq!(oracle.confidence, 39);
+        assert_eq!(amm.oracle_violation(), None);
+
+        let mut account_map = usdc_plus_account_map();
+        account_map.remove(&usdc_oracle::ID);
+        let err = usdc_plus_amm().update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "missing_account");
+
+        // The oracle has to be the one Drift prices the spot market with.
+        let mut amm = usdc_plus_amm();
+        amm.usdc_oracle = Pubkey::new_unique();
+        let mut account_map = usdc_plus_account_map();
+        let oracle = account_map.remove(&usdc_oracle::ID).unwrap();
+        account_map.insert(amm.usdc_oracle, oracle);
+        let err = amm.update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "invalid_account");
+    }
+
+    #[test]
+    fn test_reflect_amm_update_keeps_valid_accounts() {
+        let mut amm = updated_amm();
+        let spot_market = amm.spot_market;
+
+        // A larger deposit, read together with a spot market priced by
+        // another oracle. The spot market is dropped, the deposit kept.
+        let mut account_map = usdc_plus_account_map();
+        let user = &mut account_map.get_mut(&DRIFT_USER).unwrap().data;
+        user[104..112]
+            .copy_from_slice(&(2 * 65_712_776_259_718u64).to_le_bytes());
+        account_map
+            .get_mut(&amm.drift_usdc_spot_market)
+            .unwrap()
+            .data[40..72]
+            .copy_from_slice(Pubkey::new_unique().as_ref());
+
+        let err = amm.update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "invalid_account");
+        assert_eq!(amm.spot_market, spot_market);
+        assert_eq!(amm.drift_scaled_balance, 2 * 65_712_776_259_718);
+        assert!(amm.protocol_tvl > PROTOCOL_TVL);
+        assert_eq!(amm.state.as_ref().unwrap().protocol_tvl, amm.protocol_tvl);
+
+        // A controller usdc-plus-exchange cannot value is dropped with the
+        // exchange components it would move, while a new oracle price in
+        // the same batch applies.
+        let mut amm = updated_amm();
+        let mut account_map = usdc_plus_account_map();
+        account_map
+            .get_mut(&amm.usdc_plus_controller)
+            .unwrap()
+            .data
+            .truncate(43);
+        account_map.get_mut(&usdc_oracle::ID).unwrap().data[8..16]
+            .copy_from_slice(&99_990_000i64.to_le_bytes());
+        let err = amm.update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "exchange_components");
+        assert_eq!(amm.oracle_price.unwrap().price, 999_900);
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+        assert_eq!(
+            amm.account_cache[&amm.usdc_plus_controller],
+            usdc_plus_account_map()[&amm.usdc_plus_controller]
+        );
+    }
+
+    #[test]
+    fn test_reflect_amm_update_finds_usdc_position() {
+        // Offsets of the first spot position in the Drift user fixture.
+        const MARKET_INDEX: usize = 104 + 32;
+        const BALANCE_TYPE: usize = 104 + 34;
+
+        let update_with_user = |edit: fn(&mut [u8])| {
+            let mut account_map = usdc_plus_account_map();
+            edit(&mut account_map.get_mut(&DRIFT_USER).unwrap().data);
+            usdc_plus_amm().update(&account_map)
+        };
+
+        assert!(update_with_user(|_| {}).is_ok());
+        for edit in [
+            // The deposit is in another market.
+            (|data| data[MARKET_INDEX] = 1) as fn(&mut [u8]),
+            // The USDC position is a borrow.
+            |data| data[BALANCE_TYPE] = 1,
+            // The deposit moved out of the slot usdc-plus-exchange reads.
+            |data| {
+                let (first, second) = data[104..184].split_at_mut(40);
+                second.copy_from_slice(first);
+                first.fill(0);
+            },
+        ] {
+            let err = update_with_user(edit).unwrap_err();
+            assert_eq!(ReflectAmmError::kind_of(&err), "invalid_account");
+        }
+    }
+
+    #[test]
+    fn test_reflect_amm_oracle_guard() {
+        // USDC at 0.985 with the default 100 bps guard.
+        let amm = updated_amm_with_oracle(98_500_000, 0);
+        assert!(!amm.is_active());
+        assert!(matches!(
+            amm.oracle_violation(),
+            Some(OracleViolation::Depeg { price: 985_000, .. })
+        ));
+        let err = amm
+            .quote(&QuoteParams {
+                amount: 100_000_000,
+                input_mint: usdc_mint::ID,
+                output_mint: RECEIPT_MINT,
+                swap_mode: SwapMode::ExactIn,
+            })
+            .unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "oracle_guard");
+
+        // A looser guard keeps it active.
+        let amm = amm.with_oracle_guard(OracleGuard {
+            max_depeg_bps: 200,
+            ..OracleGuard::default()
+        });
+        assert!(amm.is_active());
+        quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+
+        // A 3% confidence interval is too wide for Drift.
+        let amm = updated_amm_with_oracle(100_000_000, 3_000_000);
+        assert!(!amm.is_active());
+        assert!(matches!(
+            amm.oracle_violation(),
+            Some(OracleViolation::TooUncertain {
+                confidence_bps: 300,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reflect_amm_token_2022_receipt_mint() {
+        let amm = updated_amm();
+        assert_eq!(amm.receipt_token_program, token_program::ID);
+        assert_eq!(amm.receipt_mint_extensions, MintExtensions::default());
+        let base_mint = quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+        let base_redeem = quote(&amm, 100_000_000, false, SwapMode::ExactIn);
+
+        // The same supply on Token-2022, with a 0.5% transfer fee.
+        let transfer_fee = TransferFee {
+            epoch: 0,
+            maximum_fee: u64::MAX,
+            basis_points: 50,
+        };
+        let mut account_map = usdc_plus_account_map();
+        let mint = account_map.get_mut(&RECEIPT_MINT).unwrap();
+        mint.data = token::tests::token_2022_mint(
+            token::mint_supply(&mint.data).unwrap(),
+            transfer_fee,
+            transfer_fee,
+        );
+        mint.owner = token_2022_program::ID;
+        let mut amm = usdc_plus_amm();
+        amm.update(&account_map).unwrap();
+        assert_eq!(amm.receipt_token_program, token_2022_program::ID);
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+
+        // The fee is withheld from the receipt tokens either way.
+        let mint = quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+        assert_eq!(
+            mint.out_amount,
+            base_mint.out_amount - transfer_fee.fee_on(base_mint.out_amount)
+        );
+        let redeem = quote(&amm, 100_000_000, false, SwapMode::ExactIn);
+        assert!(redeem.out_amount < base_redeem.out_amount);
+
+        // Exact out covers it.
+        let exact_out = quote(&amm, mint.out_amount, true, SwapMode::ExactOut);
+        assert!(exact_out.in_amount <= 100_000_000);
+        let exact_out = quote(&amm, 100_000_000, false, SwapMode::ExactOut);
+        let exact_in =
+            quote(&amm, exact_out.in_amount, false, SwapMode::ExactIn);
+        assert!(exact_in.out_amount >= 100_000_000);
+
+        // USDC still moves with SPL Token; the receipt mint's program and
+        // ATA follow the mint.
+        let user = Pubkey::new_unique();
+        let accounts = amm.user_token_accounts(&user);
+        assert_eq!(
+            accounts.receipt,
+            pda::find_associated_token_address_with_program(
+                &user,
+                &RECEIPT_MINT,
+                &token_2022_program::ID
+            )
+        );
+        let account_metas = amm
+            .build_mint_instruction(
+                user,
+                accounts.usdc,
+                accounts.receipt,
+                100_000_000,
+                0,
+            )
+            .unwrap()
+            .accounts;
+        assert_eq!(account_metas.len(), 22);
+        assert_eq!(account_metas[16].pubkey, token_program::ID);
+        assert_eq!(
+            account_metas[21],
+            AccountMeta::new_readonly(token_2022_program::ID, false)
+        );
+
+        // Anything else owning the mint is rejected.
+        account_map.get_mut(&RECEIPT_MINT).unwrap().owner =
+            Pubkey::new_unique();
+        let err = usdc_plus_amm().update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "invalid_account");
+    }
+
+    #[test]
+    fn test_reflect_amm_updates_from_batches() {
+        let clock_ref = ClockRef::default();
+        clock_ref.slot.store(1_000, Ordering::Relaxed);
+        let mut amm = usdc_plus_amm().with_clock(clock_ref.clone());
+        assert_eq!(amm.missing_accounts(), amm.get_accounts_to_update());
+
+        // The first batch lacks the Drift accounts.
+        let mut account_map = usdc_plus_account_map();
+        let mut later = AccountMap::default();
+        for pubkey in [amm.usdc_plus_drift_user_acc, amm.drift_usdc_spot_market]
+        {
+            later.insert(pubkey, account_map.remove(&pubkey).unwrap());
+        }
+        let err = amm.update(&account_map).unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "missing_account");
+        assert_eq!(
+            amm.missing_accounts(),
+            vec![amm.usdc_plus_drift_user_acc, amm.drift_usdc_spot_market]
+        );
+        assert!(!amm.is_active());
+
+        amm.update(&later).unwrap();
+        assert!(amm.missing_accounts().is_empty());
+        assert!(amm.is_active());
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+        assert_eq!(amm.effective_supply, EFFECTIVE_SUPPLY);
+
+        // An empty batch says nothing about the accounts: the state keeps
+        // its stamp and ages with the clock.
+        let stamp = amm.last_update;
+        clock_ref.slot.store(1_050, Ordering::Relaxed);
+        amm.update(&AccountMap::default()).unwrap();
+        assert_eq!(amm.last_update, stamp);
+        assert_eq!(amm.state_age().unwrap().slots, 50);
+
+        // Neither does data that does not decode, which is not cached.
+        let mut controller = account_map[&amm.usdc_plus_controller].clone();
+        controller.data.truncate(8);
+        let broken =
+            AccountMap::from_iter([(amm.usdc_plus_controller, controller)]);
+        assert!(amm.update(&broken).is_err());
+        assert_eq!(amm.last_update, stamp);
+        assert_eq!(
+            amm.account_cache[&amm.usdc_plus_controller],
+            account_map[&amm.usdc_plus_controller]
+        );
+        assert_eq!(amm.effective_supply, EFFECTIVE_SUPPLY);
+    }
+
+    #[test]
+    fn test_reflect_amm_update_decodes_only_changed_accounts() {
+        let mut amm = updated_amm();
+        // Marks what was decoded before: data that did not change is not
+        // decoded again, so the mark survives.
+        amm.decoded.controller.as_mut().unwrap().index = 7;
+
+        let mut account_map = usdc_plus_account_map();
+        account_map.get_mut(&usdc_oracle::ID).unwrap().data[8..16]
+            .copy_from_slice(&99_990_000i64.to_le_bytes());
+        amm.update(&account_map).unwrap();
+        assert_eq!(amm.oracle_price.unwrap().price, 999_900);
+        assert_eq!(amm.state.as_ref().unwrap().controller.index, 7);
+        assert_eq!(amm.protocol_tvl, PROTOCOL_TVL);
+
+        // A new supply recomputes the exchange components.
+        let mint = &mut account_map.get_mut(&RECEIPT_MINT).unwrap().data;
+        mint[36..44]
+            .copy_from_slice(&(EFFECTIVE_SUPPLY + 1_000_000).to_le_bytes());
+        amm.update(&account_map).unwrap();
+        assert_eq!(amm.effective_supply, EFFECTIVE_SUPPLY + 1_000_000);
+    }
+
+    #[test]
+    fn test_reflect_amm_errors_have_kinds() {
+        let mut account_map = usdc_plus_account_map();
+        account_map.remove(&CONTROLLER_USDC_ATA);
+        let err = usdc_plus_amm().update(&account_map).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ReflectAmmError>(),
+            Some(ReflectAmmError::MissingAccount(key))
+                if *key == CONTROLLER_USDC_ATA
+        ));
+
+        let jupiter_program = Pubkey::new_unique();
+        let mut swap_params = SwapParams {
+            swap_mode: SwapMode::ExactIn,
+            in_amount: 100_000_000,
+            out_amount: 99_000_000,
+            source_mint: usdc_mint::ID,
+            destination_mint: usdc_mint::ID,
+            source_token_account: Pubkey::new_unique(),
+            destination_token_account: Pubkey::new_unique(),
+            token_transfer_authority: Pubkey::new_unique(),
+            quote_mint_to_referrer: None,
+            jupiter_program_id: &jupiter_program,
+            missing_dynamic_accounts_as_default: false,
+        };
+        let err = updated_amm()
+            .get_swap_and_account_metas(&swap_params)
+            .err()
+            .unwrap();
+        assert_eq!(ReflectAmmError::kind_of(&err), "invalid_mint_pair");
+
+        swap_params.destination_mint = RECEIPT_MINT;
+        let err = updated_amm()
+            .get_swap_and_account_metas(&swap_params)
+            .err()
+            .unwrap();
+        assert_eq!(ReflectAmmError::kind_of(&err), "unsupported_swap_variant");
+    }
+
+    #[test]
+    fn test_reflect_amm_quote_rejects_invalid_pairs() {
+        let amm = updated_amm();
+        let random_mint = Pubkey::new_unique();
+
+        for (input_mint, output_mint) in [
+            (usdc_mint::ID, usdc_mint::ID),
+            (RECEIPT_MINT, RECEIPT_MINT),
+            (random_mint, usdc_mint::ID),
+            (RECEIPT_MINT, random_mint),
+        ] {
+            let err = amm
+                .quote(&QuoteParams {
+                    amount: 100_000_000,
+                    input_mint,
+                    output_mint,
+                    swap_mode: SwapMode::ExactIn,
+                })
+                .unwrap_err();
+            assert_eq!(ReflectAmmError::kind_of(&err), "invalid_mint_pair");
+        }
+    }
+
+    #[test]
+    fn test_reflect_amm_quote_before_update() {
+        let err = usdc_plus_amm()
+            .quote(&QuoteParams {
+                amount: 100_000_000,
+                input_mint: usdc_mint::ID,
+                output_mint: RECEIPT_MINT,
+                swap_mode: SwapMode::ExactIn,
+            })
+            .unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "not_updated");
+    }
+
+    #[test]
+    fn test_reflect_amm_state_age_and_max_staleness() {
+        let clock_ref = ClockRef::default();
+        clock_ref.slot.store(1_000, Ordering::Relaxed);
+        clock_ref
+            .unix_timestamp
+            .store(1_756_546_906, Ordering::Relaxed);
+
+        let mut amm = usdc_plus_amm()
+            .with_clock(clock_ref.clone())
+            .with_max_staleness_slots(10);
+        assert_eq!(amm.state_age(), None);
+        amm.update(&usdc_plus_account_map()).unwrap();
+
+        // Both measured from the update, not from Drift's last accrual.
+        let stamp = amm.last_update.unwrap();
+        assert_eq!(stamp.slot, 1_000);
+        assert_eq!(stamp.unix_timestamp, 1_756_546_906);
+        assert_eq!(stamp.spot_market_last_interest_ts, 1_756_546_839);
+        assert_eq!(
+            amm.state_age(),
+            Some(StateAge {
+                slots: 0,
+                seconds: 0
+            })
+        );
+        quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+
+        // The router's clock moves on without a new update.
+        clock_ref.slot.store(1_011, Ordering::Relaxed);
+        clock_ref
+            .unix_timestamp
+            .store(1_756_546_910, Ordering::Relaxed);
+        assert_eq!(
+            amm.state_age(),
+            Some(StateAge {
+                slots: 11,
+                seconds: 4
+            })
+        );
+        let err = amm
+            .quote(&QuoteParams {
+                amount: 100_000_000,
+                input_mint: usdc_mint::ID,
+                output_mint: RECEIPT_MINT,
+                swap_mode: SwapMode::ExactIn,
+            })
+            .unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "stale_state");
+
+        amm.update(&usdc_plus_account_map()).unwrap();
+        quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+    }
+
+    #[test]
+    fn test_reflect_amm_stalled_feed_goes_stale() {
+        let clock_ref = ClockRef::default();
+        clock_ref.slot.store(1_000, Ordering::Relaxed);
+        let mut amm = usdc_plus_amm()
+            .with_clock(clock_ref.clone())
+            .with_max_staleness_slots(10);
+        let mut account_map = usdc_plus_account_map();
+        amm.update(&account_map).unwrap();
+
+        // Every account but the oracle keeps arriving.
+        let oracle = account_map.remove(&usdc_oracle::ID).unwrap();
+        for slot in [1_005, 1_011] {
+            clock_ref.slot.store(slot, Ordering::Relaxed);
+            amm.update(&account_map).unwrap();
+        }
+        assert_eq!(amm.account_slots[&amm.usdc_plus_mint], 1_011);
+        assert_eq!(amm.last_update.unwrap().slot, 1_000);
+        let err = amm
+            .quote(&QuoteParams {
+                amount: 100_000_000,
+                input_mint: usdc_mint::ID,
+                output_mint: RECEIPT_MINT,
+                swap_mode: SwapMode::ExactIn,
+            })
+            .unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "stale_state");
+
+        // Slots a subscription observed the accounts at win over the
+        // clock's.
+        let oracle_map = AccountMap::from_iter([(usdc_oracle::ID, oracle)]);
+        amm.update_observed(
+            &oracle_map,
+            &HashMap::from([(usdc_oracle::ID, 1_003)]),
+        )
+        .unwrap();
+        assert_eq!(amm.last_update.unwrap().slot, 1_003);
+        assert!(amm.state_age().unwrap().slots > 5);
+
+        amm.update(&oracle_map).unwrap();
+        assert_eq!(amm.last_update.unwrap().slot, 1_011);
+        quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+    }
+
+    #[test]
+    fn test_reflect_amm_quote_projects_interest() {
+        let clock_ref = ClockRef::default();
+        let mut amm = usdc_plus_amm().with_clock(clock_ref.clone());
+        amm.update(&usdc_plus_account_map()).unwrap();
+        assert_eq!(amm.drift_scaled_balance, 65_712_776_259_718);
+
+        // Without a clock, or at the last accrual, nothing is projected.
+        assert_eq!(amm.projected_protocol_tvl(), PROTOCOL_TVL);
+        let last_interest_ts = amm.spot_market.last_interest_ts;
+        clock_ref
+            .unix_timestamp
+            .store(last_interest_ts, Ordering::Relaxed);
+        assert_eq!(amm.projected_protocol_tvl(), PROTOCOL_TVL);
+
+        // A day later the vault has earned a day of Drift deposit interest.
+        clock_ref
+            .unix_timestamp
+            .store(last_interest_ts + 86_400, Ordering::Relaxed);
+        let projected = amm.projected_protocol_tvl();
+        // Drift truncates the period rate before applying it.
+        let daily_rate = amm.spot_market.deposit_rate() * 86_400 / 31_536_000;
+        let expected = PROTOCOL_TVL as u128 * daily_rate / 1_000_000;
+        assert!(projected > PROTOCOL_TVL);
+        assert!(
+            (projected - PROTOCOL_TVL).abs_diff(expected as u64) <= 1,
+            "{projected} vs {expected}"
+        );
+
+        // USDC+ is worth more, so a deposit mints less.
+        let mint = quote(&amm, 100_000_000, true, SwapMode::ExactIn);
+        assert!(mint.out_amount < 98_643_856);
+        assert_eq!(
+            mint.out_amount,
+            exchange::compute_tokens_from_usdc(
+                100_000_000,
+                projected,
+                EFFECTIVE_SUPPLY
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            amm.price_per_share(),
+            Decimal::from(projected) / Decimal::from(EFFECTIVE_SUPPLY)
+        );
+    }
+
+    #[test]
+    fn test_reflect_amm_projection_matches_accrued_update() {
+        let clock_ref = ClockRef::default();
+        let mut amm = usdc_plus_amm().with_clock(clock_ref.clone());
+        let mut account_map = usdc_plus_account_map();
+        amm.update(&account_map).unwrap();
+
+        let later = amm.spot_market.last_interest_ts + 86_400;
+        clock_ref.unix_timestamp.store(later, Ordering::Relaxed);
+        let projected = amm.projected_protocol_tvl();
+        assert!(projected > PROTOCOL_TVL);
+
+        // Drift accrues the same day of interest on chain: the TVL
+        // usdc-plus-exchange computes from it is the projected one.
+        let interest =
+            amm.spot_market.projected_cumulative_deposit_interest(later);
+        let spot_market =
+            &mut account_map.get_mut(&usdc_spot_market::ID).unwrap().data;
+        spot_market[464..480].copy_from_slice(&interest.to_le_bytes());
+        spot_market[568..576].copy_from_slice(&later.to_le_bytes());
+        amm.update(&account_map).unwrap();
+        assert_eq!(amm.protocol_tvl, projected);
+        assert_eq!(amm.projected_protocol_tvl(), projected);
+    }
+
+    #[test]
+    fn test_reflect_amm_price_per_share() {
+        assert_eq!(usdc_plus_amm().price_per_share(), Decimal::ONE);
+        assert_eq!(
+            updated_amm().price_per_share(),
+            Decimal::from(PROTOCOL_TVL) / Decimal::from(EFFECTIVE_SUPPLY)
+        );
+    }
+
     #[test]
     fn test_reflect_amm_quote_invalid_mint() {
-        let amm = ReflectAmm::new();
+        let amm = updated_amm();
 
         let invalid_mint = Pubkey::new_unique();
         let result = amm.quote(&QuoteParams {
             amount: 100_000_000,
             input_mint: invalid_mint,
-            output_mint: usdc_plus_mint::ID,
+            output_mint: RECEIPT_MINT,
             swap_mode: SwapMode::ExactIn,
         });
 
         assert!(result.is_err(), "Should fail with invalid input mint");
     }
 
+    #[cfg(feature = "custom-swap")]
     #[test]
     fn test_reflect_amm_swap_and_account_metas_deposit() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
 
         let user = Pubkey::new_unique();
         let user_usdc_ata = Pubkey::new_unique();
@@ -446,7 +2086,7 @@ mod tests {
             in_amount: 100_000_000,
             out_amount: 99_000_000,
             source_mint: usdc_mint::ID,
-            destination_mint: usdc_plus_mint::ID,
+            destination_mint: RECEIPT_MINT,
             source_token_account: user_usdc_ata,
             destination_token_account: user_usdc_plus_ata,
             token_transfer_authority: user,
@@ -455,17 +2095,21 @@ mod tests {
             missing_dynamic_accounts_as_default: false,
         };
 
-        let result = amm.get_swap_and_account_metas(&swap_params).unwrap();
+        let result = amm
+            .get_reflect_swap_and_account_metas(&swap_params)
+            .unwrap();
 
+        assert_eq!(result.swap, ReflectSwapVariant::Mint);
         assert!(!result.account_metas.is_empty());
         // First account should be the user (signer)
         assert_eq!(result.account_metas[0].pubkey, user);
         assert!(result.account_metas[0].is_signer);
     }
 
+    #[cfg(feature = "custom-swap")]
     #[test]
     fn test_reflect_amm_swap_and_account_metas_withdraw() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
 
         let user = Pubkey::new_unique();
         let user_usdc_ata = Pubkey::new_unique();
@@ -476,7 +2120,7 @@ mod tests {
             swap_mode: SwapMode::ExactIn,
             in_amount: 100_000_000,
             out_amount: 101_000_000,
-            source_mint: usdc_plus_mint::ID,
+            source_mint: RECEIPT_MINT,
             destination_mint: usdc_mint::ID,
             source_token_account: user_usdc_plus_ata,
             destination_token_account: user_usdc_ata,
@@ -486,8 +2130,11 @@ mod tests {
             missing_dynamic_accounts_as_default: false,
         };
 
-        let result = amm.get_swap_and_account_metas(&swap_params).unwrap();
+        let result = amm
+            .get_reflect_swap_and_account_metas(&swap_params)
+            .unwrap();
 
+        assert_eq!(result.swap, ReflectSwapVariant::Redeem);
         assert!(!result.account_metas.is_empty());
         assert_eq!(result.account_metas[0].pubkey, user);
         assert!(result.account_metas[0].is_signer);
@@ -495,7 +2142,7 @@ mod tests {
 
     #[test]
     fn test_reflect_amm_swap_invalid_mint_pair() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
 
         let user = Pubkey::new_unique();
         let invalid_mint = Pubkey::new_unique();
@@ -506,7 +2153,7 @@ mod tests {
             in_amount: 100_000_000,
             out_amount: 99_000_000,
             source_mint: invalid_mint,
-            destination_mint: usdc_plus_mint::ID,
+            destination_mint: RECEIPT_MINT,
             source_token_account: Pubkey::new_unique(),
             destination_token_account: Pubkey::new_unique(),
             token_transfer_authority: user,
@@ -521,7 +2168,7 @@ mod tests {
 
     #[test]
     fn test_reflect_amm_swap_same_mint() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
 
         let user = Pubkey::new_unique();
         let jupiter_program = Pubkey::new_unique();
@@ -547,9 +2194,269 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "custom-swap")]
+    #[test]
+    fn test_reflect_amm_swap_uses_own_receipt_mint() {
+        let receipt_mint = Pubkey::new_unique();
+        let amm = ReflectAmm::from_strategy(
+            &ReflectStrategy {
+                controller: Pubkey::new_unique(),
+                receipt_mint,
+                drift_sub_account_id: 1,
+                ..ReflectStrategy::usdc_plus()
+            },
+            ClockRef::default(),
+        );
+        assert_eq!(amm.get_reserve_mints(), vec![usdc_mint::ID, receipt_mint]);
+
+        let user = Pubkey::new_unique();
+        let jupiter_program = Pubkey::new_unique();
+        let mut swap_params = SwapParams {
+            swap_mode: SwapMode::ExactIn,
+            in_amount: 100_000_000,
+            out_amount: 99_000_000,
+            source_mint: usdc_mint::ID,
+            destination_mint: receipt_mint,
+            source_token_account: Pubkey::new_unique(),
+            destination_token_account: Pubkey::new_unique(),
+            token_transfer_authority: user,
+            quote_mint_to_referrer: None,
+            jupiter_program_id: &jupiter_program,
+            missing_dynamic_accounts_as_default: false,
+        };
+
+        let result = amm
+            .get_reflect_swap_and_account_metas(&swap_params)
+            .unwrap();
+        assert_eq!(result.account_metas[2].pubkey, amm.usdc_plus_controller);
+        assert_eq!(result.account_metas[7].pubkey, receipt_mint);
+
+        // USDC+ belongs to another market.
+        swap_params.destination_mint = RECEIPT_MINT;
+        assert!(amm
+            .get_reflect_swap_and_account_metas(&swap_params)
+            .is_err());
+    }
+
+    #[test]
+    fn test_reflect_amm_swap_never_emits_foreign_variant() {
+        let amm = usdc_plus_amm();
+        let jupiter_program = Pubkey::new_unique();
+
+        let swap_params = SwapParams {
+            swap_mode: SwapMode::ExactIn,
+            in_amount: 100_000_000,
+            out_amount: 99_000_000,
+            source_mint: usdc_mint::ID,
+            destination_mint: RECEIPT_MINT,
+            source_token_account: Pubkey::new_unique(),
+            destination_token_account: Pubkey::new_unique(),
+            token_transfer_authority: Pubkey::new_unique(),
+            quote_mint_to_referrer: None,
+            jupiter_program_id: &jupiter_program,
+            missing_dynamic_accounts_as_default: false,
+        };
+
+        assert!(
+            amm.get_swap_and_account_metas(&swap_params).is_err(),
+            "Interface has no Reflect variant to return"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "custom-swap")]
+    fn test_reflect_amm_router_swap_path() {
+        // The router holds markets as `dyn Amm` and keeps the Reflect ones
+        // by key.
+        let reflect_amm = updated_amm();
+        let amm: Box<dyn Amm + Send + Sync> = reflect_amm.clone_amm();
+        let reflect_amms = HashMap::from([(reflect_amm.key(), reflect_amm)]);
+
+        let user = Pubkey::new_unique();
+        let jupiter_program = Pubkey::new_unique();
+        let accounts = reflect_amms[&amm.key()].user_token_accounts(&user);
+        let swap_params = SwapParams {
+            swap_mode: SwapMode::ExactIn,
+            in_amount: 100_000_000,
+            out_amount: 98_000_000,
+            source_mint: usdc_mint::ID,
+            destination_mint: RECEIPT_MINT,
+            source_token_account: accounts.usdc,
+            destination_token_account: accounts.receipt,
+            token_transfer_authority: user,
+            quote_mint_to_referrer: None,
+            jupiter_program_id: &jupiter_program,
+            missing_dynamic_accounts_as_default: false,
+        };
+        assert!(amm.get_swap_and_account_metas(&swap_params).is_err());
+
+        let ReflectSwapAndAccountMetas {
+            swap,
+            account_metas,
+        } = reflect_amms[&amm.key()]
+            .get_reflect_swap_and_account_metas(&swap_params)
+            .unwrap();
+        let instruction = Instruction {
+            program_id: amm.program_id(),
+            accounts: account_metas,
+            data: swap.instruction_data(100_000_000, 98_000_000),
+        };
+        assert_eq!(
+            instruction,
+            reflect_amms[&amm.key()]
+                .build_mint_instruction(
+                    user,
+                    accounts.usdc,
+                    accounts.receipt,
+                    100_000_000,
+                    98_000_000,
+                )
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_reflect_amm_build_instructions_match_router_accounts() {
+        let amm = updated_amm();
+        let user = Pubkey::new_unique();
+        let user_usdc_ata = Pubkey::new_unique();
+        let user_receipt_ata = Pubkey::new_unique();
+        let jupiter_program = Pubkey::new_unique();
+
+        for (is_deposit, min_out) in [(true, 98_000_000), (false, 101_000_000)]
+        {
+            let (source_mint, destination_mint) = if is_deposit {
+                (usdc_mint::ID, RECEIPT_MINT)
+            } else {
+                (RECEIPT_MINT, usdc_mint::ID)
+            };
+            let (source_token_account, destination_token_account) =
+                if is_deposit {
+                    (user_usdc_ata, user_receipt_ata)
+                } else {
+                    (user_receipt_ata, user_usdc_ata)
+                };
+            let (variant, account_metas) = amm
+                .build_swap(&SwapParams {
+                    swap_mode: SwapMode::ExactIn,
+                    in_amount: 100_000_000,
+                    out_amount: min_out,
+                    source_mint,
+                    destination_mint,
+                    source_token_account,
+                    destination_token_account,
+                    token_transfer_authority: user,
+                    quote_mint_to_referrer: None,
+                    jupiter_program_id: &jupiter_program,
+                    missing_dynamic_accounts_as_default: false,
+                })
+                .unwrap();
+
+            let build = if is_deposit {
+                ReflectAmm::build_mint_instruction
+            } else {
+                ReflectAmm::build_redeem_instruction
+            };
+            let instruction = build(
+                &amm,
+                user,
+                user_usdc_ata,
+                user_receipt_ata,
+                100_000_000,
+                min_out,
+            )
+            .unwrap();
+
+            assert_eq!(instruction.program_id, reflect::ID);
+            assert_eq!(instruction.accounts, account_metas);
+            assert_eq!(
+                instruction.data,
+                variant.instruction_data(100_000_000, min_out)
+            );
+        }
+    }
+
+    #[test]
+    fn test_reflect_amm_checks_user_token_accounts() {
+        let amm = updated_amm().with_user_token_account_checks(true);
+        let user = Pubkey::new_unique();
+        let accounts = amm.user_token_accounts(&user);
+        let jupiter_program = Pubkey::new_unique();
+        let mut swap_params = SwapParams {
+            swap_mode: SwapMode::ExactIn,
+            in_amount: 100_000_000,
+            out_amount: 98_000_000,
+            source_mint: usdc_mint::ID,
+            destination_mint: RECEIPT_MINT,
+            source_token_account: accounts.usdc,
+            destination_token_account: accounts.receipt,
+            token_transfer_authority: user,
+            quote_mint_to_referrer: None,
+            jupiter_program_id: &jupiter_program,
+            missing_dynamic_accounts_as_default: false,
+        };
+        amm.build_swap(&swap_params).unwrap();
+
+        let unknown = Pubkey::new_unique();
+        swap_params.destination_token_account = unknown;
+        assert!(matches!(
+            amm.build_swap(&swap_params),
+            Err(ReflectAmmError::UnexpectedTokenAccount { expected, actual })
+                if expected == accounts.receipt && actual == unknown
+        ));
+        // Unchecked by default.
+        updated_amm().build_swap(&swap_params).unwrap();
+
+        // Minting pays out to the receipt ATA, redeeming to the USDC one.
+        let payer = Pubkey::new_unique();
+        let create = amm
+            .create_destination_token_account_instruction(&payer, &user, true);
+        assert_eq!(create.accounts[1].pubkey, accounts.receipt);
+        assert_eq!(create.accounts[5].pubkey, amm.receipt_token_program);
+        let create = amm
+            .create_destination_token_account_instruction(&payer, &user, false);
+        assert_eq!(create.accounts[1].pubkey, accounts.usdc);
+    }
+
+    #[test]
+    fn test_reflect_amm_checks_user_token_account_owners() {
+        let amm = updated_amm();
+        let user = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let token_account = |mint: &Pubkey, owner: &Pubkey, program| Account {
+            data: token::tests::token_account(mint, owner),
+            owner: program,
+            ..Account::default()
+        };
+        let check = |account: &Account| {
+            amm.check_user_token_account(&user, &pubkey, account)
+                .map_err(|e| e.kind())
+        };
+
+        for mint in [usdc_mint::ID, RECEIPT_MINT] {
+            check(&token_account(&mint, &user, token_program::ID)).unwrap();
+            // Someone else's account, or one the mint's program does not
+            // own.
+            let other = Pubkey::new_unique();
+            assert_eq!(
+                check(&token_account(&mint, &other, token_program::ID)),
+                Err("invalid_account")
+            );
+            assert_eq!(
+                check(&token_account(&mint, &user, token_2022_program::ID)),
+                Err("invalid_account")
+            );
+        }
+        let other_mint = Pubkey::new_unique();
+        assert_eq!(
+            check(&token_account(&other_mint, &user, token_program::ID)),
+            Err("invalid_account")
+        );
+    }
+
     #[test]
     fn test_reflect_amm_clone() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
         let cloned = amm.clone_amm();
 
         assert_eq!(cloned.label(), amm.label());
@@ -559,15 +2466,16 @@ mod tests {
 
     #[test]
     fn test_reflect_amm_trait_methods() {
-        let amm = ReflectAmm::new();
+        let amm = usdc_plus_amm();
 
         assert_eq!(amm.label(), REFLECT_LABEL);
         assert_eq!(amm.program_id(), reflect::ID);
-        assert_eq!(amm.key(), usdc_controller::ID);
+        assert_eq!(amm.key(), CONTROLLER);
         assert!(!amm.has_dynamic_accounts());
         assert!(!amm.requires_update_for_reserve_mints());
         assert!(amm.supports_exact_out());
         assert!(!amm.unidirectional());
-        assert!(amm.is_active());
+        assert!(!amm.is_active());
+        assert!(updated_amm().is_active());
     }
 }
@@ -0,0 +1,238 @@
+use std::fmt;
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+
+/// Anchor discriminator of Drift's `PythLazerOracle` account.
+pub const PYTH_LAZER_ORACLE_DISCRIMINATOR: [u8; 8] =
+    [159, 7, 161, 249, 34, 81, 121, 133];
+
+// Offsets into the zero-copy `PythLazerOracle`, discriminator included.
+const PRICE_OFFSET: usize = 8;
+const PUBLISH_TIME_OFFSET: usize = 16;
+const POSTED_SLOT_OFFSET: usize = 24;
+const EXPONENT_OFFSET: usize = 32;
+const CONF_OFFSET: usize = 40;
+const PYTH_LAZER_ORACLE_LEN: usize = 48;
+
+/// Drift's `PRICE_PRECISION`, 10^6.
+pub const PRICE_PRECISION: u64 = 1_000_000;
+const PRICE_PRECISION_EXP: i32 = 6;
+const BPS_PRECISION: u128 = 10_000;
+
+/// The USDC price Drift reads from the strategy's spot market oracle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OraclePrice {
+    /// USD per USDC in `PRICE_PRECISION`.
+    pub price: i64,
+    /// Confidence interval in `PRICE_PRECISION`.
+    pub confidence: u64,
+    pub publish_time: u64,
+    pub posted_slot: u64,
+}
+
+impl OraclePrice {
+    pub fn try_deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            data.len() >= PYTH_LAZER_ORACLE_LEN,
+            "Oracle account too short: {} bytes",
+            data.len()
+        );
+        ensure!(
+            data[..8] == PYTH_LAZER_ORACLE_DISCRIMINATOR,
+            "Account is not a Drift PythLazerOracle"
+        );
+
+        let exponent = read_u32(data, EXPONENT_OFFSET) as i32;
+        let price = read_u64(data, PRICE_OFFSET) as i64;
+        let confidence = read_u64(data, CONF_OFFSET);
+        Ok(OraclePrice {
+            price: rescale(price as i128, exponent)?
+                .try_into()
+                .context("Oracle price overflows")?,
+            confidence: rescale(confidence as i128, exponent)?
+                .try_into()
+                .context("Oracle confidence overflows")?,
+            publish_time: read_u64(data, PUBLISH_TIME_OFFSET),
+            posted_slot: read_u64(data, POSTED_SLOT_OFFSET),
+        })
+    }
+
+    /// Distance from the one dollar peg in bps, rounded up.
+    pub fn depeg_bps(&self) -> u128 {
+        let delta =
+            (self.price as i128 - PRICE_PRECISION as i128).unsigned_abs();
+        (delta * BPS_PRECISION).div_ceil(PRICE_PRECISION as u128)
+    }
+
+    /// Confidence interval relative to the price in bps, rounded up.
+    /// `None` for a price Drift would reject outright.
+    pub fn confidence_bps(&self) -> Option<u128> {
+        if self.price <= 0 {
+            return None;
+        }
+        Some(
+            (self.confidence as u128 * BPS_PRECISION)
+                .div_ceil(self.price as u128),
+        )
+    }
+}
+
+/// Limits on the oracle beyond which the AMM reports itself inactive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OracleGuard {
+    /// Largest distance of USDC from one dollar, in bps.
+    pub max_depeg_bps: u16,
+    /// Widest confidence interval relative to the price, in bps. Drift
+    /// refuses the oracle past its guard rails, 2% by default.
+    pub max_confidence_bps: u16,
+}
+
+impl Default for OracleGuard {
+    fn default() -> Self {
+        OracleGuard {
+            max_depeg_bps: 100,
+            max_confidence_bps: 200,
+        }
+    }
+}
+
+impl OracleGuard {
+    pub fn check(&self, oracle: &OraclePrice) -> Result<(), OracleViolation> {
+        let Some(confidence_bps) = oracle.confidence_bps() else {
+            return Err(OracleViolation::InvalidPrice {
+                price: oracle.price,
+            });
+        };
+        if confidence_bps > self.max_confidence_bps as u128 {
+            return Err(OracleViolation::TooUncertain {
+                confidence_bps,
+                max_bps: self.max_confidence_bps,
+            });
+        }
+        let depeg_bps = oracle.depeg_bps();
+        if depeg_bps > self.max_depeg_bps as u128 {
+            return Err(OracleViolation::Depeg {
+                price: oracle.price,
+                max_bps: self.max_depeg_bps,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The oracle is outside an `OracleGuard`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleViolation {
+    InvalidPrice { price: i64 },
+    TooUncertain { confidence_bps: u128, max_bps: u16 },
+    Depeg { price: i64, max_bps: u16 },
+}
+
+impl fmt::Display for OracleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OracleViolation::InvalidPrice { price } => {
+                write!(f, "Oracle price {} is not positive", price)
+            }
+            OracleViolation::TooUncertain {
+                confidence_bps,
+                max_bps,
+            } => write!(
+                f,
+                "Oracle confidence of {} bps is wider than {} bps",
+                confidence_bps, max_bps
+            ),
+            OracleViolation::Depeg { price, max_bps } => write!(
+                f,
+                "USDC at {} is more than {} bps from its peg",
+                price, max_bps
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OracleViolation {}
+
+/// Converts a value with a Pyth exponent to `PRICE_PRECISION`.
+fn rescale(value: i128, exponent: i32) -> anyhow::Result<i128> {
+    let shift = exponent + PRICE_PRECISION_EXP;
+    let factor = 10i128
+        .checked_pow(shift.unsigned_abs())
+        .context("Oracle exponent out of range")?;
+    if shift >= 0 {
+        value.checked_mul(factor).context("Oracle value overflows")
+    } else {
+        Ok(value / factor)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oracle_data(price: i64, conf: u64, exponent: i32) -> Vec<u8> {
+        let mut data = vec![0u8; PYTH_LAZER_ORACLE_LEN];
+        data[..8].copy_from_slice(&PYTH_LAZER_ORACLE_DISCRIMINATOR);
+        data[PRICE_OFFSET..PRICE_OFFSET + 8]
+            .copy_from_slice(&price.to_le_bytes());
+        data[EXPONENT_OFFSET..EXPONENT_OFFSET + 4]
+            .copy_from_slice(&exponent.to_le_bytes());
+        data[CONF_OFFSET..CONF_OFFSET + 8].copy_from_slice(&conf.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_oracle_price_rescales_to_price_precision() {
+        let oracle =
+            OraclePrice::try_deserialize(&oracle_data(99_987_500, 3_900, -8))
+                .unwrap();
+        assert_eq!(oracle.price, 999_875);
+        assert_eq!(oracle.confidence, 39);
+        assert_eq!(oracle.depeg_bps(), 2);
+        assert_eq!(oracle.confidence_bps(), Some(1));
+
+        let mut data = oracle_data(1, 0, -8);
+        data[0] = 0;
+        assert!(OraclePrice::try_deserialize(&data).is_err());
+    }
+
+    #[test]
+    fn test_oracle_guard() {
+        let guard = OracleGuard::default();
+        let price = |price, confidence| OraclePrice {
+            price,
+            confidence,
+            ..OraclePrice::default()
+        };
+
+        assert_eq!(guard.check(&price(999_875, 39)), Ok(()));
+        assert_eq!(guard.check(&price(990_000, 0)), Ok(()));
+        assert_eq!(
+            guard.check(&price(989_999, 0)),
+            Err(OracleViolation::Depeg {
+                price: 989_999,
+                max_bps: 100,
+            })
+        );
+        assert_eq!(
+            guard.check(&price(1_000_000, 20_001)),
+            Err(OracleViolation::TooUncertain {
+                confidence_bps: 201,
+                max_bps: 200,
+            })
+        );
+        assert_eq!(
+            guard.check(&price(0, 0)),
+            Err(OracleViolation::InvalidPrice { price: 0 })
+        );
+    }
+}
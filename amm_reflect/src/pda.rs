@@ -0,0 +1,84 @@
+use solana_sdk::pubkey::Pubkey;
+
+use crate::constants::{associated_token_program, token_program};
+
+pub const DRIFT_STATE_SEED: &[u8] = b"drift_state";
+pub const DRIFT_SIGNER_SEED: &[u8] = b"drift_signer";
+pub const DRIFT_USER_SEED: &[u8] = b"user";
+pub const DRIFT_USER_STATS_SEED: &[u8] = b"user_stats";
+pub const DRIFT_SPOT_MARKET_SEED: &[u8] = b"spot_market";
+pub const DRIFT_SPOT_MARKET_VAULT_SEED: &[u8] = b"spot_market_vault";
+
+pub fn find_drift_state(drift_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[DRIFT_STATE_SEED], drift_program).0
+}
+
+/// Drift signer, the authority of every spot market vault.
+pub fn find_drift_signer(drift_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[DRIFT_SIGNER_SEED], drift_program).0
+}
+
+pub fn find_drift_user(
+    drift_program: &Pubkey,
+    authority: &Pubkey,
+    sub_account_id: u16,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            DRIFT_USER_SEED,
+            authority.as_ref(),
+            &sub_account_id.to_le_bytes(),
+        ],
+        drift_program,
+    )
+    .0
+}
+
+pub fn find_drift_user_stats(
+    drift_program: &Pubkey,
+    authority: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[DRIFT_USER_STATS_SEED, authority.as_ref()],
+        drift_program,
+    )
+    .0
+}
+
+pub fn find_drift_spot_market(
+    drift_program: &Pubkey,
+    market_index: u16,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[DRIFT_SPOT_MARKET_SEED, &market_index.to_le_bytes()],
+        drift_program,
+    )
+    .0
+}
+
+pub fn find_drift_spot_market_vault(
+    drift_program: &Pubkey,
+    market_index: u16,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[DRIFT_SPOT_MARKET_VAULT_SEED, &market_index.to_le_bytes()],
+        drift_program,
+    )
+    .0
+}
+
+pub fn find_associated_token_address(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    find_associated_token_address_with_program(owner, mint, &token_program::ID)
+}
+
+pub fn find_associated_token_address_with_program(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &associated_token_program::ID,
+    )
+    .0
+}
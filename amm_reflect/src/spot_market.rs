@@ -0,0 +1,461 @@
+use anyhow::ensure;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminator of Drift's `SpotMarket` account.
+pub const SPOT_MARKET_DISCRIMINATOR: [u8; 8] =
+    [100, 177, 8, 107, 168, 65, 65, 39];
+
+// Offsets into the Borsh-packed `SpotMarket`, discriminator included.
+const ORACLE_OFFSET: usize = 40;
+const INSURANCE_FUND_TOTAL_FACTOR_OFFSET: usize = 408;
+const DEPOSIT_BALANCE_OFFSET: usize = 432;
+const BORROW_BALANCE_OFFSET: usize = 448;
+const CUMULATIVE_DEPOSIT_INTEREST_OFFSET: usize = 464;
+const CUMULATIVE_BORROW_INTEREST_OFFSET: usize = 480;
+const WITHDRAW_GUARD_THRESHOLD_OFFSET: usize = 528;
+const MAX_TOKEN_DEPOSITS_OFFSET: usize = 536;
+const DEPOSIT_TOKEN_TWAP_OFFSET: usize = 544;
+const LAST_INTEREST_TS_OFFSET: usize = 568;
+const OPTIMAL_UTILIZATION_OFFSET: usize = 668;
+const OPTIMAL_BORROW_RATE_OFFSET: usize = 672;
+const MAX_BORROW_RATE_OFFSET: usize = 676;
+const DECIMALS_OFFSET: usize = 680;
+const MARKET_INDEX_OFFSET: usize = 684;
+const SPOT_MARKET_LEN: usize = 776;
+
+/// Precision of the cumulative interest, 10^10.
+const SPOT_CUMULATIVE_INTEREST_PRECISION_EXP: u32 = 10;
+/// Precision of scaled balances, 10^9.
+const SPOT_BALANCE_PRECISION_EXP: u32 = 9;
+
+pub const SPOT_UTILIZATION_PRECISION: u128 = 1_000_000;
+pub const SPOT_RATE_PRECISION: u128 = 1_000_000;
+const IF_FACTOR_PRECISION: u128 = 1_000_000;
+pub const ONE_YEAR: u128 = 31_536_000;
+
+// A Drift `User` holds its spot positions after authority, delegate and name.
+const USER_SPOT_POSITIONS_OFFSET: usize = 8 + 32 + 32 + 32;
+const SPOT_POSITION_LEN: usize = 40;
+const SPOT_POSITION_COUNT: usize = 8;
+// Within a `SpotPosition`, after the scaled balance, open orders and
+// cumulative deposits.
+const SPOT_POSITION_MARKET_INDEX_OFFSET: usize = 32;
+const SPOT_POSITION_BALANCE_TYPE_OFFSET: usize = 34;
+const SPOT_BALANCE_TYPE_BORROW: u8 = 1;
+
+/// The parts of a Drift spot market that bound what can move through it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DriftSpotMarket {
+    /// Oracle Drift prices the market's token with.
+    pub oracle: Pubkey,
+    pub deposit_balance: u128,
+    pub borrow_balance: u128,
+    pub cumulative_deposit_interest: u128,
+    pub cumulative_borrow_interest: u128,
+    pub withdraw_guard_threshold: u64,
+    /// Zero when deposits are uncapped.
+    pub max_token_deposits: u64,
+    pub deposit_token_twap: u64,
+    pub last_interest_ts: i64,
+    /// Share of borrow interest kept by the insurance fund.
+    pub insurance_fund_total_factor: u32,
+    pub optimal_utilization: u32,
+    pub optimal_borrow_rate: u32,
+    pub max_borrow_rate: u32,
+    pub decimals: u32,
+    pub market_index: u16,
+}
+
+impl DriftSpotMarket {
+    pub fn try_deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            data.len() >= SPOT_MARKET_LEN,
+            "Spot market account too short: {} bytes",
+            data.len()
+        );
+        ensure!(
+            data[..8] == SPOT_MARKET_DISCRIMINATOR,
+            "Account is not a Drift SpotMarket"
+        );
+
+        Ok(DriftSpotMarket {
+            oracle: Pubkey::try_from(&data[ORACLE_OFFSET..ORACLE_OFFSET + 32])
+                .unwrap(),
+            deposit_balance: read_u128(data, DEPOSIT_BALANCE_OFFSET),
+            borrow_balance: read_u128(data, BORROW_BALANCE_OFFSET),
+            cumulative_deposit_interest: read_u128(
+                data,
+                CUMULATIVE_DEPOSIT_INTEREST_OFFSET,
+            ),
+            cumulative_borrow_interest: read_u128(
+                data,
+                CUMULATIVE_BORROW_INTEREST_OFFSET,
+            ),
+            withdraw_guard_threshold: read_u64(
+                data,
+                WITHDRAW_GUARD_THRESHOLD_OFFSET,
+            ),
+            max_token_deposits: read_u64(data, MAX_TOKEN_DEPOSITS_OFFSET),
+            deposit_token_twap: read_u64(data, DEPOSIT_TOKEN_TWAP_OFFSET),
+            last_interest_ts: read_u64(data, LAST_INTEREST_TS_OFFSET) as i64,
+            insurance_fund_total_factor: read_u32(
+                data,
+                INSURANCE_FUND_TOTAL_FACTOR_OFFSET,
+            ),
+            optimal_utilization: read_u32(data, OPTIMAL_UTILIZATION_OFFSET),
+            optimal_borrow_rate: read_u32(data, OPTIMAL_BORROW_RATE_OFFSET),
+            max_borrow_rate: read_u32(data, MAX_BORROW_RATE_OFFSET),
+            decimals: read_u32(data, DECIMALS_OFFSET),
+            market_index: u16::from_le_bytes([
+                data[MARKET_INDEX_OFFSET],
+                data[MARKET_INDEX_OFFSET + 1],
+            ]),
+        })
+    }
+
+    fn precision_decrease(&self) -> u128 {
+        10u128.pow(
+            (SPOT_CUMULATIVE_INTEREST_PRECISION_EXP
+                + SPOT_BALANCE_PRECISION_EXP)
+                .saturating_sub(self.decimals),
+        )
+    }
+
+    /// Deposited tokens, rounded down.
+    pub fn deposit_token_amount(&self) -> u128 {
+        self.deposit_token_amount_at(
+            self.deposit_balance,
+            self.cumulative_deposit_interest,
+        )
+    }
+
+    /// Tokens behind a scaled deposit balance at the given cumulative
+    /// deposit interest, rounded down.
+    pub fn deposit_token_amount_at(
+        &self,
+        scaled_balance: u128,
+        cumulative_deposit_interest: u128,
+    ) -> u128 {
+        scaled_balance * cumulative_deposit_interest / self.precision_decrease()
+    }
+
+    /// Borrowed tokens, rounded up.
+    pub fn borrow_token_amount(&self) -> u128 {
+        self.borrow_token_amount_at(
+            self.borrow_balance,
+            self.cumulative_borrow_interest,
+        )
+    }
+
+    /// Tokens owed on a scaled borrow balance at the given cumulative borrow
+    /// interest, rounded up.
+    pub fn borrow_token_amount_at(
+        &self,
+        scaled_balance: u128,
+        cumulative_borrow_interest: u128,
+    ) -> u128 {
+        (scaled_balance * cumulative_borrow_interest)
+            .div_ceil(self.precision_decrease())
+    }
+
+    /// Borrows over deposits, in `SPOT_UTILIZATION_PRECISION`.
+    pub fn utilization(&self) -> u128 {
+        let deposits = self.deposit_token_amount();
+        if deposits == 0 {
+            return 0;
+        }
+        (self.borrow_token_amount() * SPOT_UTILIZATION_PRECISION / deposits)
+            .min(SPOT_UTILIZATION_PRECISION)
+    }
+
+    /// Annual borrow rate in `SPOT_RATE_PRECISION`: linear up to the optimal
+    /// utilization, then a steeper line up to `max_borrow_rate` at 100%.
+    pub fn borrow_rate(&self) -> u128 {
+        let utilization = self.utilization();
+        let optimal_utilization = self.optimal_utilization as u128;
+        let optimal_borrow_rate = self.optimal_borrow_rate as u128;
+
+        if utilization <= optimal_utilization {
+            if optimal_utilization == 0 {
+                return optimal_borrow_rate;
+            }
+            return utilization * optimal_borrow_rate / optimal_utilization;
+        }
+
+        let surplus = utilization - optimal_utilization;
+        let surplus_range =
+            SPOT_UTILIZATION_PRECISION.saturating_sub(optimal_utilization);
+        let rate_range =
+            (self.max_borrow_rate as u128).saturating_sub(optimal_borrow_rate);
+        optimal_borrow_rate + surplus * rate_range / surplus_range.max(1)
+    }
+
+    /// Annual rate paid to depositors in `SPOT_RATE_PRECISION`, after the
+    /// insurance fund's cut.
+    pub fn deposit_rate(&self) -> u128 {
+        let lender_share = IF_FACTOR_PRECISION
+            .saturating_sub(self.insurance_fund_total_factor as u128);
+        self.borrow_rate() * self.utilization() / SPOT_UTILIZATION_PRECISION
+            * lender_share
+            / IF_FACTOR_PRECISION
+    }
+
+    /// Cumulative deposit interest Drift would record if it accrued at
+    /// `unix_timestamp`, assuming utilization holds since the last accrual.
+    pub fn projected_cumulative_deposit_interest(
+        &self,
+        unix_timestamp: i64,
+    ) -> u128 {
+        let elapsed = unix_timestamp.saturating_sub(self.last_interest_ts);
+        if elapsed <= 0 {
+            return self.cumulative_deposit_interest;
+        }
+        let accrued = self.cumulative_deposit_interest
+            * (self.deposit_rate() * elapsed as u128 / ONE_YEAR)
+            / SPOT_RATE_PRECISION;
+        self.cumulative_deposit_interest + accrued
+    }
+
+    /// Tokens that can still be deposited before `max_token_deposits`.
+    pub fn remaining_deposit_capacity(&self) -> u64 {
+        if self.max_token_deposits == 0 {
+            return u64::MAX;
+        }
+        clamp_u64(
+            (self.max_token_deposits as u128)
+                .saturating_sub(self.deposit_token_amount()),
+        )
+    }
+
+    /// Tokens that can be withdrawn right now: what is not lent out, further
+    /// limited by Drift's withdraw guard once deposits exceed its threshold.
+    pub fn withdrawable_amount(&self) -> u64 {
+        let deposits = self.deposit_token_amount();
+        let liquidity = deposits.saturating_sub(self.borrow_token_amount());
+
+        if deposits <= self.withdraw_guard_threshold as u128 {
+            return clamp_u64(liquidity);
+        }
+
+        // Deposits may not fall more than max(25%, threshold) below the
+        // 24h average in one go.
+        let twap = self.deposit_token_twap as u128;
+        let min_deposits = twap.saturating_sub(
+            (twap / 4).max((self.withdraw_guard_threshold as u128).min(twap)),
+        );
+        clamp_u64(liquidity.min(deposits.saturating_sub(min_deposits)))
+    }
+}
+
+/// One spot position of a Drift `User`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserSpotPosition {
+    pub scaled_balance: u64,
+    pub market_index: u16,
+    pub is_borrow: bool,
+}
+
+impl UserSpotPosition {
+    /// Deposited tokens, zero for a borrow.
+    pub fn deposit_token_amount(&self, spot_market: &DriftSpotMarket) -> u128 {
+        if self.is_borrow {
+            return 0;
+        }
+        spot_market.deposit_token_amount_at(
+            self.scaled_balance as u128,
+            spot_market.cumulative_deposit_interest,
+        )
+    }
+
+    /// Borrowed tokens, zero for a deposit.
+    pub fn borrow_token_amount(&self, spot_market: &DriftSpotMarket) -> u128 {
+        if !self.is_borrow {
+            return 0;
+        }
+        spot_market.borrow_token_amount_at(
+            self.scaled_balance as u128,
+            spot_market.cumulative_borrow_interest,
+        )
+    }
+}
+
+/// Spot position at `position_index` of a Drift `User`.
+pub fn user_spot_position(
+    user_data: &[u8],
+    position_index: usize,
+) -> anyhow::Result<UserSpotPosition> {
+    let offset =
+        USER_SPOT_POSITIONS_OFFSET + position_index * SPOT_POSITION_LEN;
+    ensure!(
+        user_data.len() >= offset + SPOT_POSITION_LEN,
+        "User account too short: {} bytes",
+        user_data.len()
+    );
+    let market_index_offset = offset + SPOT_POSITION_MARKET_INDEX_OFFSET;
+    Ok(UserSpotPosition {
+        scaled_balance: read_u64(user_data, offset),
+        market_index: u16::from_le_bytes([
+            user_data[market_index_offset],
+            user_data[market_index_offset + 1],
+        ]),
+        is_borrow: user_data[offset + SPOT_POSITION_BALANCE_TYPE_OFFSET]
+            == SPOT_BALANCE_TYPE_BORROW,
+    })
+}
+
+/// The position of a Drift `User` in `market_index` and its slot among the
+/// user's spot positions. Free slots, which Drift leaves at market index 0
+/// with no balance, never match.
+pub fn find_user_spot_position(
+    user_data: &[u8],
+    market_index: u16,
+) -> anyhow::Result<Option<(usize, UserSpotPosition)>> {
+    for position_index in 0..SPOT_POSITION_COUNT {
+        let position = user_spot_position(user_data, position_index)?;
+        if position.market_index == market_index && position.scaled_balance != 0
+        {
+            return Ok(Some((position_index, position)));
+        }
+    }
+    Ok(None)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u128(data: &[u8], offset: usize) -> u128 {
+    u128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+pub(crate) fn clamp_u64(amount: u128) -> u64 {
+    amount.min(u64::MAX as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot_market() -> DriftSpotMarket {
+        DriftSpotMarket {
+            oracle: Pubkey::new_unique(),
+            deposit_balance: 1_000 * 10u128.pow(9),
+            borrow_balance: 600 * 10u128.pow(9),
+            cumulative_deposit_interest: 10u128.pow(10),
+            cumulative_borrow_interest: 10u128.pow(10),
+            withdraw_guard_threshold: 100_000_000,
+            max_token_deposits: 0,
+            deposit_token_twap: 1_000_000_000,
+            last_interest_ts: 0,
+            insurance_fund_total_factor: 100_000,
+            optimal_utilization: 800_000,
+            optimal_borrow_rate: 50_000,
+            max_borrow_rate: 1_000_000,
+            decimals: 6,
+            market_index: 0,
+        }
+    }
+
+    #[test]
+    fn test_spot_market_token_amounts() {
+        let market = spot_market();
+        assert_eq!(market.deposit_token_amount(), 1_000_000_000);
+        assert_eq!(market.borrow_token_amount(), 600_000_000);
+        assert_eq!(market.remaining_deposit_capacity(), u64::MAX);
+
+        let capped = DriftSpotMarket {
+            max_token_deposits: 1_200_000_000,
+            ..market
+        };
+        assert_eq!(capped.remaining_deposit_capacity(), 200_000_000);
+    }
+
+    #[test]
+    fn test_spot_market_withdrawable_amount() {
+        // Guard allows 25% of the twap out, liquidity allows 40%.
+        assert_eq!(spot_market().withdrawable_amount(), 250_000_000);
+
+        // Below the threshold only liquidity counts.
+        let small = DriftSpotMarket {
+            withdraw_guard_threshold: u64::MAX,
+            ..spot_market()
+        };
+        assert_eq!(small.withdrawable_amount(), 400_000_000);
+    }
+
+    #[test]
+    fn test_spot_market_interest_rates() {
+        // 60% utilization on the first slope.
+        let market = spot_market();
+        assert_eq!(market.utilization(), 600_000);
+        assert_eq!(market.borrow_rate(), 37_500);
+        // 3.75% * 60%, less the insurance fund's 10%.
+        assert_eq!(market.deposit_rate(), 20_250);
+
+        // 90% utilization is halfway up the second slope.
+        let busy = DriftSpotMarket {
+            borrow_balance: 900 * 10u128.pow(9),
+            ..market
+        };
+        assert_eq!(busy.borrow_rate(), 525_000);
+    }
+
+    #[test]
+    fn test_spot_market_projected_interest() {
+        let market = spot_market();
+        assert_eq!(
+            market.projected_cumulative_deposit_interest(-1),
+            market.cumulative_deposit_interest
+        );
+
+        // A year at 2.025% grows the index by the same.
+        assert_eq!(
+            market.projected_cumulative_deposit_interest(ONE_YEAR as i64),
+            10_202_500_000
+        );
+    }
+
+    #[test]
+    fn test_user_spot_position() {
+        let index = 2;
+        let offset = USER_SPOT_POSITIONS_OFFSET + index * SPOT_POSITION_LEN;
+        let mut user = vec![0u8; offset + SPOT_POSITION_LEN];
+        user[offset..offset + 8]
+            .copy_from_slice(&5_000_000_000u64.to_le_bytes());
+        user[offset + SPOT_POSITION_MARKET_INDEX_OFFSET] = 1;
+
+        let market = spot_market();
+        let position = user_spot_position(&user, index).unwrap();
+        assert_eq!(position.market_index, 1);
+        assert_eq!(position.deposit_token_amount(&market), 5_000_000);
+        assert_eq!(position.borrow_token_amount(&market), 0);
+
+        user[offset + SPOT_POSITION_BALANCE_TYPE_OFFSET] =
+            SPOT_BALANCE_TYPE_BORROW;
+        let position = user_spot_position(&user, index).unwrap();
+        assert_eq!(position.deposit_token_amount(&market), 0);
+        assert_eq!(position.borrow_token_amount(&market), 5_000_000);
+
+        assert!(user_spot_position(&user, index + 1).is_err());
+    }
+
+    #[test]
+    fn test_find_user_spot_position() {
+        let mut user = vec![0u8; USER_SPOT_POSITIONS_OFFSET + 8 * 40];
+        let offset = USER_SPOT_POSITIONS_OFFSET + 3 * SPOT_POSITION_LEN;
+        user[offset..offset + 8].copy_from_slice(&7u64.to_le_bytes());
+        user[offset + SPOT_POSITION_MARKET_INDEX_OFFSET] = 1;
+
+        let (index, position) =
+            find_user_spot_position(&user, 1).unwrap().unwrap();
+        assert_eq!(index, 3);
+        assert_eq!(position.scaled_balance, 7);
+        // Free slots sit at market 0 but hold nothing.
+        assert_eq!(find_user_spot_position(&user, 0).unwrap(), None);
+        assert!(find_user_spot_position(&user[..200], 1).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::controller::DriftUsdcController;
+
+/// What `update` decoded from a strategy's accounts, for dashboards and
+/// risk checks that would otherwise need their own decoder.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflectState {
+    /// Slot of the oldest account the state was decoded from.
+    pub slot: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub controller_key: Pubkey,
+    pub controller: DriftUsdcController,
+    pub receipt_mint_supply: u64,
+
+    // The strategy's Drift USDC position, in tokens.
+    pub drift_deposit_balance: u64,
+    pub drift_borrow_balance: u64,
+    pub cumulative_deposit_interest: u128,
+    pub cumulative_borrow_interest: u128,
+
+    // Exchange components
+    pub protocol_tvl: u64,
+    pub effective_supply: u64,
+    /// USDC per receipt token, one for an empty vault.
+    pub exchange_rate: Decimal,
+}
+
+/// USDC per receipt token. Both have 6 decimals, so raw amounts divide
+/// directly.
+pub fn exchange_rate(protocol_tvl: u64, effective_supply: u64) -> Decimal {
+    Decimal::from(protocol_tvl)
+        .checked_div(Decimal::from(effective_supply))
+        .unwrap_or(Decimal::ONE)
+}
@@ -0,0 +1,295 @@
+use anyhow::ensure;
+use jupiter_amm_interface::{ClockRef, KeyedAccount};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    config::ReflectConfig,
+    constants::{
+        usdc_controller, usdc_oracle, usdc_plus_mint, USDC_SPOT_MARKET_INDEX,
+    },
+    controller::DriftUsdcController,
+    ReflectAmm,
+};
+
+/// Accounts that identify one Reflect product: a controller, the receipt
+/// token it mints and the Drift position backing it.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflectStrategy {
+    #[serde_as(as = "DisplayFromStr")]
+    pub controller: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub receipt_mint: Pubkey,
+    pub drift_sub_account_id: u16,
+    pub spot_market_index: u16,
+    #[serde_as(as = "DisplayFromStr")]
+    pub oracle: Pubkey,
+}
+
+impl ReflectStrategy {
+    /// Strategy 0, USDC+.
+    pub fn usdc_plus() -> Self {
+        ReflectStrategy {
+            controller: usdc_controller::ID,
+            receipt_mint: usdc_plus_mint::ID,
+            drift_sub_account_id: 0,
+            spot_market_index: USDC_SPOT_MARKET_INDEX,
+            oracle: usdc_oracle::ID,
+        }
+    }
+
+    /// Describes the strategy owned by a decoded controller account on the
+    /// cluster of `config`. A `DriftUsdcController` deposits into Drift's
+    /// USDC market, index 0 on every cluster, priced by the cluster's USDC
+    /// oracle.
+    pub fn from_controller(
+        config: &ReflectConfig,
+        key: Pubkey,
+        controller: &DriftUsdcController,
+    ) -> Self {
+        ReflectStrategy {
+            controller: key,
+            receipt_mint: controller.receipt_mint,
+            drift_sub_account_id: controller.index,
+            spot_market_index: USDC_SPOT_MARKET_INDEX,
+            oracle: config.usdc_oracle,
+        }
+    }
+}
+
+/// Set of Reflect strategies on one cluster, each quoted by its own
+/// `ReflectAmm`.
+#[derive(Clone, Debug)]
+pub struct StrategyRegistry {
+    config: ReflectConfig,
+    strategies: Vec<ReflectStrategy>,
+}
+
+impl StrategyRegistry {
+    /// Empty registry on the cluster described by `config`.
+    pub fn new(config: ReflectConfig) -> Self {
+        StrategyRegistry {
+            config,
+            strategies: vec![],
+        }
+    }
+
+    /// Registry holding the strategies listed in `config`.
+    pub fn from_config(config: ReflectConfig) -> anyhow::Result<Self> {
+        let strategies = config.strategies.clone();
+        let mut registry = StrategyRegistry::new(config);
+        for strategy in strategies {
+            registry.register(strategy)?;
+        }
+        Ok(registry)
+    }
+
+    /// Strategies live on mainnet.
+    pub fn mainnet() -> Self {
+        StrategyRegistry {
+            strategies: vec![ReflectStrategy::usdc_plus()],
+            config: ReflectConfig::mainnet(),
+        }
+    }
+
+    /// Decodes every controller account into a strategy.
+    pub fn from_keyed_accounts(
+        config: ReflectConfig,
+        keyed_accounts: &[KeyedAccount],
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            config.usdc_oracle != Pubkey::default(),
+            "{}: usdc_oracle is not configured",
+            config.cluster
+        );
+        let mut registry = StrategyRegistry::new(config);
+        for keyed_account in keyed_accounts {
+            ensure!(
+                keyed_account.account.owner == registry.config.reflect_program,
+                "Account {} is not owned by the Reflect program",
+                keyed_account.key
+            );
+            let controller = DriftUsdcController::try_deserialize(
+                &keyed_account.account.data,
+            )?;
+            registry.register(ReflectStrategy::from_controller(
+                &registry.config,
+                keyed_account.key,
+                &controller,
+            ))?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds a strategy. Controllers and receipt mints must be unique.
+    pub fn register(
+        &mut self,
+        strategy: ReflectStrategy,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            self.get(&strategy.controller).is_none(),
+            "Controller {} is already registered",
+            strategy.controller
+        );
+        ensure!(
+            self.get_by_receipt_mint(&strategy.receipt_mint).is_none(),
+            "Receipt mint {} is already registered",
+            strategy.receipt_mint
+        );
+        self.strategies.push(strategy);
+        Ok(())
+    }
+
+    pub fn get(&self, controller: &Pubkey) -> Option<&ReflectStrategy> {
+        self.strategies.iter().find(|s| s.controller == *controller)
+    }
+
+    pub fn get_by_receipt_mint(
+        &self,
+        receipt_mint: &Pubkey,
+    ) -> Option<&ReflectStrategy> {
+        self.strategies
+            .iter()
+            .find(|s| s.receipt_mint == *receipt_mint)
+    }
+
+    pub fn strategies(&self) -> &[ReflectStrategy] {
+        &self.strategies
+    }
+
+    pub fn config(&self) -> &ReflectConfig {
+        &self.config
+    }
+
+    /// One AMM per strategy, so the router sees every product as its own
+    /// market. All of them read slots and time from `clock_ref`.
+    pub fn build_amms(&self, clock_ref: &ClockRef) -> Vec<ReflectAmm> {
+        self.strategies
+            .iter()
+            .map(|strategy| {
+                ReflectAmm::from_config(
+                    &self.config,
+                    strategy,
+                    clock_ref.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use solana_sdk::account::Account;
+
+    use super::*;
+    use crate::{
+        constants::{
+            drift_state, drift_vault, reflect, reflect_user_account_strategy_0,
+        },
+        controller::DRIFT_USDC_CONTROLLER_DISCRIMINATOR,
+    };
+
+    #[test]
+    fn test_registry_mainnet_matches_defaults() {
+        let clock_ref = ClockRef::default();
+        let amms = StrategyRegistry::mainnet().build_amms(&clock_ref);
+        assert_eq!(amms.len(), 1);
+
+        // The AMMs follow the router's clock.
+        clock_ref.slot.store(42, Ordering::Relaxed);
+        assert_eq!(amms[0].clock.slot(), 42);
+
+        let amm = &amms[0];
+        let expected = ReflectAmm::new();
+        assert_eq!(amm.usdc_plus_controller, expected.usdc_plus_controller);
+        assert_eq!(amm.usdc_plus_mint, expected.usdc_plus_mint);
+        assert_eq!(
+            amm.usdc_plus_drift_user_acc,
+            reflect_user_account_strategy_0::ID
+        );
+        assert_eq!(amm.drift_state, drift_state::ID);
+        assert_eq!(amm.drift_vault, drift_vault::ID);
+    }
+
+    #[test]
+    fn test_registry_builds_independent_amms() {
+        let mut registry = StrategyRegistry::mainnet();
+        let second = ReflectStrategy {
+            controller: Pubkey::new_unique(),
+            receipt_mint: Pubkey::new_unique(),
+            drift_sub_account_id: 1,
+            spot_market_index: 0,
+            oracle: usdc_oracle::ID,
+        };
+        registry.register(second.clone()).unwrap();
+
+        let amms = registry.build_amms(&ClockRef::default());
+        assert_eq!(amms.len(), 2);
+        assert_eq!(amms[1].usdc_plus_controller, second.controller);
+        assert_eq!(amms[1].usdc_plus_mint, second.receipt_mint);
+        assert_ne!(
+            amms[0].usdc_plus_drift_user_acc,
+            amms[1].usdc_plus_drift_user_acc
+        );
+        assert_ne!(amms[0].drift_user_stats, amms[1].drift_user_stats);
+    }
+
+    #[test]
+    fn test_registry_from_keyed_accounts_uses_cluster_oracle() {
+        let mut data = vec![0u8; 1_071];
+        data[..8].copy_from_slice(&DRIFT_USDC_CONTROLLER_DISCRIMINATOR);
+        data[9] = 1;
+        data[11..43].copy_from_slice(Pubkey::new_unique().as_ref());
+        let keyed_account = KeyedAccount {
+            key: Pubkey::new_unique(),
+            account: Account {
+                lamports: 1,
+                data,
+                owner: reflect::ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+            params: None,
+        };
+
+        // Devnet has no USDC oracle preset.
+        let devnet = ReflectConfig::devnet();
+        assert!(StrategyRegistry::from_keyed_accounts(
+            devnet.clone(),
+            std::slice::from_ref(&keyed_account)
+        )
+        .is_err());
+
+        let oracle = Pubkey::new_unique();
+        let registry = StrategyRegistry::from_keyed_accounts(
+            ReflectConfig {
+                usdc_oracle: oracle,
+                ..devnet
+            },
+            &[keyed_account],
+        )
+        .unwrap();
+        assert_eq!(registry.strategies()[0].oracle, oracle);
+        assert_eq!(registry.strategies()[0].drift_sub_account_id, 1);
+        assert_eq!(
+            registry.build_amms(&ClockRef::default())[0].usdc_oracle,
+            oracle
+        );
+    }
+
+    #[test]
+    fn test_registry_rejects_duplicates() {
+        let mut registry = StrategyRegistry::mainnet();
+        assert!(registry.register(ReflectStrategy::usdc_plus()).is_err());
+
+        let same_mint = ReflectStrategy {
+            controller: Pubkey::new_unique(),
+            ..ReflectStrategy::usdc_plus()
+        };
+        assert!(registry.register(same_mint).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+#[cfg(feature = "custom-swap")]
+use solana_sdk::instruction::AccountMeta;
+
+// The instruction names behind these discriminators are not confirmed:
+// no Reflect IDL is vendored. Anchor rejects unknown discriminators, so a
+// wrong name fails every swap on-chain rather than calling something else.
+
+/// Anchor discriminator of `global:mint`, Reflect's USDC -> receipt swap.
+pub const MINT_DISCRIMINATOR: [u8; 8] = [51, 57, 225, 47, 182, 146, 137, 166];
+
+/// Anchor discriminator of `global:redeem`, Reflect's receipt -> USDC swap.
+pub const REDEEM_DISCRIMINATOR: [u8; 8] = [184, 12, 86, 149, 70, 196, 97, 225];
+
+/// The Reflect swap a router has to encode, selected by swap direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReflectSwapVariant {
+    Mint,
+    Redeem,
+}
+
+impl ReflectSwapVariant {
+    pub fn from_direction(is_deposit: bool) -> Self {
+        if is_deposit {
+            ReflectSwapVariant::Mint
+        } else {
+            ReflectSwapVariant::Redeem
+        }
+    }
+
+    pub fn discriminator(&self) -> [u8; 8] {
+        match self {
+            ReflectSwapVariant::Mint => MINT_DISCRIMINATOR,
+            ReflectSwapVariant::Redeem => REDEEM_DISCRIMINATOR,
+        }
+    }
+
+    /// Anchor instruction data: the discriminator, then `amount` and
+    /// `min_out` as little endian `u64`s.
+    pub fn instruction_data(&self, amount: u64, min_out: u64) -> Vec<u8> {
+        let mut data = Vec::with_capacity(24);
+        data.extend_from_slice(&self.discriminator());
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        data
+    }
+}
+
+/// Custom counterpart of `SwapAndAccountMetas` for routers whose
+/// `jupiter-amm-interface` has no Reflect `Swap` variant.
+#[cfg(feature = "custom-swap")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectSwapAndAccountMetas {
+    pub swap: ReflectSwapVariant,
+    pub account_metas: Vec<AccountMeta>,
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::hash;
+
+    use super::*;
+
+    fn anchor_discriminator(name: &str) -> [u8; 8] {
+        hash(format!("global:{name}").as_bytes()).to_bytes()[..8]
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_swap_variant_encodes_anchor_sighash() {
+        // Anchor dispatches on `sha256("global:<name>")`, so the encoded
+        // data has to start with it.
+        for (is_deposit, name) in [(true, "mint"), (false, "redeem")] {
+            let data = ReflectSwapVariant::from_direction(is_deposit)
+                .instruction_data(1, 0);
+            assert_eq!(data[..8], anchor_discriminator(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_swap_variant_instruction_data() {
+        let data = ReflectSwapVariant::Redeem.instruction_data(7, u64::MAX);
+        assert_eq!(data.len(), 24);
+        assert_eq!(data[..8], REDEEM_DISCRIMINATOR);
+        assert_eq!(data[8..16], 7u64.to_le_bytes());
+        assert_eq!(data[16..], u64::MAX.to_le_bytes());
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{bail, ensure};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::constants::{token_2022_program, token_program};
+
+const MINT_SUPPLY_OFFSET: usize = 36;
+const MINT_LEN: usize = 82;
+const TOKEN_ACCOUNT_MINT_OFFSET: usize = 0;
+const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+const TOKEN_ACCOUNT_AMOUNT_OFFSET: usize = 64;
+const TOKEN_ACCOUNT_LEN: usize = 165;
+
+// Token-2022 pads mints to the token account length, then stores the
+// account type and its extensions as type-length-value entries.
+const ACCOUNT_TYPE_OFFSET: usize = TOKEN_ACCOUNT_LEN;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const TLV_START: usize = ACCOUNT_TYPE_OFFSET + 1;
+const TLV_HEADER_LEN: usize = 4;
+
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+
+// `TransferFeeConfig`: two authorities and the withheld amount, then the
+// older and newer fees.
+const OLDER_TRANSFER_FEE_OFFSET: usize = 32 + 32 + 8;
+const TRANSFER_FEE_LEN: usize = 18;
+const TRANSFER_FEE_CONFIG_LEN: usize =
+    OLDER_TRANSFER_FEE_OFFSET + 2 * TRANSFER_FEE_LEN;
+
+const ONE_IN_BASIS_POINTS: u128 = 10_000;
+
+/// Supply of an SPL mint.
+pub fn mint_supply(data: &[u8]) -> anyhow::Result<u64> {
+    ensure!(data.len() >= MINT_LEN, "Invalid mint: {} bytes", data.len());
+    Ok(u64::from_le_bytes(
+        data[MINT_SUPPLY_OFFSET..MINT_SUPPLY_OFFSET + 8].try_into()?,
+    ))
+}
+
+/// Balance of an SPL token account.
+pub fn token_account_amount(data: &[u8]) -> anyhow::Result<u64> {
+    ensure!(
+        data.len() >= TOKEN_ACCOUNT_LEN,
+        "Invalid token account: {} bytes",
+        data.len()
+    );
+    Ok(u64::from_le_bytes(
+        data[TOKEN_ACCOUNT_AMOUNT_OFFSET..TOKEN_ACCOUNT_AMOUNT_OFFSET + 8]
+            .try_into()?,
+    ))
+}
+
+/// Mint and owner of an SPL token account.
+pub fn token_account_keys(data: &[u8]) -> anyhow::Result<(Pubkey, Pubkey)> {
+    ensure!(
+        data.len() >= TOKEN_ACCOUNT_LEN,
+        "Invalid token account: {} bytes",
+        data.len()
+    );
+    let key = |offset: usize| -> anyhow::Result<Pubkey> {
+        Ok(Pubkey::new_from_array(
+            data[offset..offset + 32].try_into()?,
+        ))
+    };
+    Ok((
+        key(TOKEN_ACCOUNT_MINT_OFFSET)?,
+        key(TOKEN_ACCOUNT_OWNER_OFFSET)?,
+    ))
+}
+
+/// Checks that `owner` is one of the SPL token programs and returns it.
+pub fn token_program_of(owner: &Pubkey) -> anyhow::Result<Pubkey> {
+    if *owner == token_program::ID || *owner == token_2022_program::ID {
+        Ok(*owner)
+    } else {
+        bail!("Owned by {}, not a token program", owner)
+    }
+}
+
+/// Token-2022 transfer fee for one epoch range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransferFee {
+    /// First epoch the fee applies to.
+    pub epoch: u64,
+    pub maximum_fee: u64,
+    pub basis_points: u16,
+}
+
+impl TransferFee {
+    fn from_bytes(data: &[u8]) -> Self {
+        TransferFee {
+            epoch: u64::from_le_bytes(data[..8].try_into().unwrap()),
+            maximum_fee: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            basis_points: u16::from_le_bytes(data[16..18].try_into().unwrap()),
+        }
+    }
+
+    /// Withheld from a transfer of `amount`, rounded up like Token-2022.
+    pub fn fee_on(&self, amount: u64) -> u64 {
+        if self.basis_points == 0 || amount == 0 {
+            return 0;
+        }
+        let fee = (amount as u128 * self.basis_points as u128)
+            .div_ceil(ONE_IN_BASIS_POINTS);
+        fee.min(self.maximum_fee as u128) as u64
+    }
+
+    /// Smallest transfer that delivers `amount` after the fee.
+    pub fn pre_fee_amount(&self, amount: u64) -> Option<u64> {
+        match (self.basis_points as u128, amount) {
+            (0, _) => Some(amount),
+            (_, 0) => Some(0),
+            (ONE_IN_BASIS_POINTS, _) => amount.checked_add(self.maximum_fee),
+            (basis_points, _) => {
+                let raw = (amount as u128 * ONE_IN_BASIS_POINTS)
+                    .div_ceil(ONE_IN_BASIS_POINTS - basis_points);
+                if raw - amount as u128 >= self.maximum_fee as u128 {
+                    amount.checked_add(self.maximum_fee)
+                } else {
+                    u64::try_from(raw).ok()
+                }
+            }
+        }
+    }
+}
+
+/// Token-2022 transfer fee schedule of a mint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransferFeeConfig {
+    pub older_transfer_fee: TransferFee,
+    pub newer_transfer_fee: TransferFee,
+}
+
+impl TransferFeeConfig {
+    pub fn epoch_fee(&self, epoch: u64) -> &TransferFee {
+        if epoch >= self.newer_transfer_fee.epoch {
+            &self.newer_transfer_fee
+        } else {
+            &self.older_transfer_fee
+        }
+    }
+}
+
+/// Token-2022 extensions of a mint that bear on receipt token amounts.
+/// Interest-bearing mints need none: they only scale UI amounts, and quotes
+/// use raw amounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MintExtensions {
+    pub transfer_fee: Option<TransferFeeConfig>,
+}
+
+impl MintExtensions {
+    /// Reads the extensions of a mint. Legacy mints have none.
+    pub fn try_deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(data.len() >= MINT_LEN, "Invalid mint: {} bytes", data.len());
+        let mut extensions = MintExtensions::default();
+        if data.len() <= ACCOUNT_TYPE_OFFSET {
+            return Ok(extensions);
+        }
+        ensure!(
+            data[ACCOUNT_TYPE_OFFSET] == ACCOUNT_TYPE_MINT,
+            "Account is not a Token-2022 mint"
+        );
+
+        let mut offset = TLV_START;
+        while offset + TLV_HEADER_LEN <= data.len() {
+            let extension_type =
+                u16::from_le_bytes([data[offset], data[offset + 1]]);
+            let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]])
+                as usize;
+            // Zeroed space left for extensions added later.
+            if extension_type == 0 {
+                break;
+            }
+            let start = offset + TLV_HEADER_LEN;
+            let Some(value) = data.get(start..start + len) else {
+                bail!("Mint extension {} is truncated", extension_type);
+            };
+
+            if extension_type == EXTENSION_TRANSFER_FEE_CONFIG {
+                ensure!(
+                    len >= TRANSFER_FEE_CONFIG_LEN,
+                    "Invalid transfer fee config: {} bytes",
+                    len
+                );
+                let newer = OLDER_TRANSFER_FEE_OFFSET + TRANSFER_FEE_LEN;
+                extensions.transfer_fee = Some(TransferFeeConfig {
+                    older_transfer_fee: TransferFee::from_bytes(
+                        &value[OLDER_TRANSFER_FEE_OFFSET..newer],
+                    ),
+                    newer_transfer_fee: TransferFee::from_bytes(
+                        &value[newer..newer + TRANSFER_FEE_LEN],
+                    ),
+                });
+            }
+            offset = start + len;
+        }
+        Ok(extensions)
+    }
+
+    /// Transfer fee in effect at `epoch`, if the mint charges one.
+    pub fn transfer_fee(&self, epoch: u64) -> Option<&TransferFee> {
+        self.transfer_fee
+            .as_ref()
+            .map(|config| config.epoch_fee(epoch))
+            .filter(|fee| fee.basis_points != 0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A Token-2022 mint with a transfer fee.
+    pub(crate) fn token_2022_mint(
+        supply: u64,
+        older: TransferFee,
+        newer: TransferFee,
+    ) -> Vec<u8> {
+        let mut data = vec![0u8; TLV_START];
+        data[MINT_SUPPLY_OFFSET..MINT_SUPPLY_OFFSET + 8]
+            .copy_from_slice(&supply.to_le_bytes());
+        data[ACCOUNT_TYPE_OFFSET] = ACCOUNT_TYPE_MINT;
+
+        let mut transfer_fee = vec![0u8; OLDER_TRANSFER_FEE_OFFSET];
+        for fee in [older, newer] {
+            transfer_fee.extend_from_slice(&fee.epoch.to_le_bytes());
+            transfer_fee.extend_from_slice(&fee.maximum_fee.to_le_bytes());
+            transfer_fee.extend_from_slice(&fee.basis_points.to_le_bytes());
+        }
+        data.extend_from_slice(&EXTENSION_TRANSFER_FEE_CONFIG.to_le_bytes());
+        data.extend_from_slice(&(transfer_fee.len() as u16).to_le_bytes());
+        data.extend_from_slice(&transfer_fee);
+        data
+    }
+
+    /// A token account of `owner` for `mint`.
+    pub(crate) fn token_account(mint: &Pubkey, owner: &Pubkey) -> Vec<u8> {
+        let mut data = vec![0u8; TOKEN_ACCOUNT_LEN];
+        data[TOKEN_ACCOUNT_MINT_OFFSET..TOKEN_ACCOUNT_MINT_OFFSET + 32]
+            .copy_from_slice(mint.as_ref());
+        data[TOKEN_ACCOUNT_OWNER_OFFSET..TOKEN_ACCOUNT_OWNER_OFFSET + 32]
+            .copy_from_slice(owner.as_ref());
+        data
+    }
+
+    #[test]
+    fn test_mint_extensions() {
+        assert_eq!(
+            MintExtensions::try_deserialize(&[0u8; MINT_LEN]).unwrap(),
+            MintExtensions::default()
+        );
+
+        let older = TransferFee {
+            epoch: 0,
+            maximum_fee: u64::MAX,
+            basis_points: 0,
+        };
+        let newer = TransferFee {
+            epoch: 700,
+            maximum_fee: 5_000_000,
+            basis_points: 50,
+        };
+        let data = token_2022_mint(1_000, older, newer);
+        assert_eq!(mint_supply(&data).unwrap(), 1_000);
+
+        let extensions = MintExtensions::try_deserialize(&data).unwrap();
+        assert_eq!(extensions.transfer_fee(699), None);
+        assert_eq!(extensions.transfer_fee(700), Some(&newer));
+
+        let mut truncated = data.clone();
+        truncated.truncate(data.len() - 1);
+        assert!(MintExtensions::try_deserialize(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_transfer_fee() {
+        let fee = TransferFee {
+            epoch: 0,
+            maximum_fee: 5_000_000,
+            basis_points: 50,
+        };
+        assert_eq!(fee.fee_on(0), 0);
+        assert_eq!(fee.fee_on(1), 1);
+        assert_eq!(fee.fee_on(100_000_000), 500_000);
+        assert_eq!(fee.fee_on(10_000_000_000), 5_000_000);
+
+        for amount in [1, 999, 99_500_000, 10_000_000_000] {
+            let pre = fee.pre_fee_amount(amount).unwrap();
+            assert_eq!(pre - fee.fee_on(pre), amount);
+            assert!(pre - 1 - fee.fee_on(pre - 1) < amount);
+        }
+    }
+
+    #[test]
+    fn test_token_account_keys() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let data = token_account(&mint, &owner);
+        assert_eq!(token_account_keys(&data).unwrap(), (mint, owner));
+        assert!(token_account_keys(&data[..TOKEN_ACCOUNT_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_token_program_of() {
+        assert_eq!(
+            token_program_of(&token_2022_program::ID).unwrap(),
+            token_2022_program::ID
+        );
+        assert!(token_program_of(&Pubkey::new_unique()).is_err());
+    }
+}
@@ -1,6 +1,33 @@
 use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
 
-use crate::constants::token_program;
+use crate::error::ReflectAmmError;
+
+/// Names of the accounts `ReflectSwap` produces, in order, for
+/// `idl::check_reflect_swap`. No Reflect IDL confirms them yet. The last two are remaining accounts for Drift; a Token-2022 receipt
+/// mint adds its token program after them.
+pub(crate) const REFLECT_SWAP_ACCOUNT_NAMES: [&str; 21] = [
+    "user",
+    "main",
+    "usdc_controller",
+    "admin_permissions",
+    "user_receipt_ata",
+    "user_usdc_ata",
+    "controller_usdc_ata",
+    "receipt_mint",
+    "drift_program",
+    "drift_state",
+    "drift_user_stats",
+    "referrer_user_stats",
+    "referrer_user",
+    "drift_user_account",
+    "drift_spot_market_vault",
+    "drift_vault",
+    "token_program",
+    "system_program",
+    "clock",
+    "usdc_oracle",
+    "drift_usdc_spot_market",
+];
 
 pub struct ReflectSwap {
     // User accounts (dynamic)
@@ -14,6 +41,10 @@ pub struct ReflectSwap {
     pub admin_permissions: Pubkey,
     pub controller_usdc_ata: Pubkey,
     pub receipt_mint: Pubkey,
+    /// SPL Token, which moves USDC.
+    pub token_program: Pubkey,
+    /// Program that owns the receipt mint and the user's receipt account.
+    pub receipt_token_program: Pubkey,
 
     // Drift accounts
     pub drift_program: Pubkey,
@@ -31,10 +62,10 @@ pub struct ReflectSwap {
 }
 
 impl TryFrom<ReflectSwap> for Vec<AccountMeta> {
-    type Error = anyhow::Error;
+    type Error = ReflectAmmError;
 
     fn try_from(swap: ReflectSwap) -> Result<Self, Self::Error> {
-        Ok(vec![
+        let mut account_metas = vec![
             // #1 - user (signer)
             AccountMeta::new(swap.user, true),
             // #2 - main
@@ -54,29 +85,102 @@ impl TryFrom<ReflectSwap> for Vec<AccountMeta> {
             // #9 - drift program
             AccountMeta::new_readonly(swap.drift_program, false),
             // #10 - drift state
-            AccountMeta::new(swap.drift_state, false),
+            AccountMeta::new_readonly(swap.drift_state, false),
             // #11 - user_stats
             AccountMeta::new(swap.drift_user_stats, false),
             // #12 - referrer_user_stats
-            AccountMeta::new(swap.referrer_user_stats, false),
+            AccountMeta::new_readonly(swap.referrer_user_stats, false),
             // #13 - referrer_user
-            AccountMeta::new(swap.referrer_user, false),
+            AccountMeta::new_readonly(swap.referrer_user, false),
             // #14 - user_account (reflect drift user)
             AccountMeta::new(swap.drift_user_account, false),
             // #15 - spot_market_vault
             AccountMeta::new(swap.drift_spot_market_vault, false),
             // #16 - drift_vault
-            AccountMeta::new(swap.drift_vault, false),
+            AccountMeta::new_readonly(swap.drift_vault, false),
             // #17 - token_program
-            AccountMeta::new_readonly(token_program::ID, false),
+            AccountMeta::new_readonly(swap.token_program, false),
             // #18 - system_program
             AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
             // #19 - clock
             AccountMeta::new_readonly(solana_sdk::sysvar::clock::ID, false),
             // #20 - usdc_oracle (remaining)
-            AccountMeta::new(swap.usdc_oracle, false),
+            AccountMeta::new_readonly(swap.usdc_oracle, false),
             // #21 - drift_usdc_spot_market (remaining)
             AccountMeta::new(swap.drift_usdc_spot_market, false),
-        ])
+        ];
+        // #22 - receipt token program (remaining), when it is not #17
+        if swap.receipt_token_program != swap.token_program {
+            account_metas.push(AccountMeta::new_readonly(
+                swap.receipt_token_program,
+                false,
+            ));
+        }
+        Ok(account_metas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReflectAmm;
+
+    /// Every account with the locks it takes and why. Mints and redeems
+    /// reach Drift through `deposit` and `withdraw` on the strategy's
+    /// existing Drift user; "Drift" marks the lock those two declare. The
+    /// referrer accounts are only written when a Drift user is created.
+    /// Reflect's own accounts keep the locks the program was first
+    /// integrated with, as its constraints are not public.
+    const ACCOUNT_LOCKS: [(&str, bool, bool, &str); 21] = [
+        // (name, writable, signer, reason)
+        ("user", true, true, "pays, signs"),
+        ("main", true, false, "Reflect"),
+        ("usdc_controller", true, false, "Reflect"),
+        ("admin_permissions", false, false, "Reflect"),
+        ("user_receipt_ata", true, false, "balance"),
+        ("user_usdc_ata", true, false, "balance"),
+        ("controller_usdc_ata", true, false, "balance"),
+        ("receipt_mint", true, false, "supply"),
+        ("drift_program", false, false, "program"),
+        ("drift_state", false, false, "Drift"),
+        ("drift_user_stats", true, false, "Drift"),
+        ("referrer_user_stats", false, false, "user creation only"),
+        ("referrer_user", false, false, "user creation only"),
+        ("drift_user_account", true, false, "Drift"),
+        ("drift_spot_market_vault", true, false, "Drift"),
+        ("drift_vault", false, false, "Drift, signer PDA"),
+        ("token_program", false, false, "program"),
+        ("system_program", false, false, "program"),
+        ("clock", false, false, "sysvar"),
+        ("usdc_oracle", false, false, "Drift"),
+        ("drift_usdc_spot_market", true, false, "Drift"),
+    ];
+
+    #[test]
+    fn test_reflect_swap_account_locks() {
+        let user = Pubkey::new_unique();
+        let account_metas = ReflectAmm::new()
+            .build_mint_instruction(
+                user,
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                1,
+                0,
+            )
+            .unwrap()
+            .accounts;
+        assert_eq!(account_metas.len(), ACCOUNT_LOCKS.len());
+
+        for (index, (meta, (name, writable, signer, _))) in
+            account_metas.iter().zip(ACCOUNT_LOCKS).enumerate()
+        {
+            assert_eq!(REFLECT_SWAP_ACCOUNT_NAMES[index], name);
+            assert_eq!(meta.is_writable, writable, "{name} writable");
+            assert_eq!(meta.is_signer, signer, "{name} signer");
+        }
+
+        let write_locks =
+            account_metas.iter().filter(|meta| meta.is_writable).count();
+        assert_eq!(write_locks, 11);
     }
 }
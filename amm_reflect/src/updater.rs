@@ -0,0 +1,601 @@
+//! Keeps a `ReflectAmm` fresh outside a router: fetches the accounts it
+//! needs on an interval, or follows their changes, applies `update` and
+//! publishes the result.
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use jupiter_amm_interface::{AccountMap, Amm, ClockRef};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    account::{from_account, Account},
+    clock::Clock,
+    pubkey::Pubkey,
+    sysvar,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time,
+};
+
+use crate::ReflectAmm;
+
+/// Source of account data, `RpcClient` in production.
+#[async_trait]
+pub trait AccountFetcher: Send + Sync {
+    /// Accounts in the order of `pubkeys`, `None` for missing ones.
+    async fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> anyhow::Result<Vec<Option<Account>>>;
+}
+
+#[async_trait]
+impl AccountFetcher for RpcClient {
+    async fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> anyhow::Result<Vec<Option<Account>>> {
+        Ok(self
+            .get_multiple_accounts_with_commitment(pubkeys, self.commitment())
+            .await?
+            .value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdaterConfig {
+    /// Time between successful updates.
+    pub interval: Duration,
+    /// Wait after the first failure, doubled after each one that follows.
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for UpdaterConfig {
+    fn default() -> Self {
+        UpdaterConfig {
+            interval: Duration::from_secs(2),
+            min_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl UpdaterConfig {
+    /// Wait before retrying after `failures` updates failed in a row.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.min_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// How the updater is doing, for health checks and alerts.
+#[derive(Clone, Debug, Default)]
+pub struct UpdaterHealth {
+    /// Slot of the last successful update.
+    pub last_success_slot: Option<u64>,
+    pub consecutive_failures: u32,
+    /// Error of the last failed update, cleared by a success.
+    pub last_error: Option<Arc<anyhow::Error>>,
+}
+
+/// One account change from a websocket or Geyser subscription.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountUpdate {
+    pub pubkey: Pubkey,
+    /// Slot the change was observed at.
+    pub slot: u64,
+    pub account: Account,
+}
+
+/// Source of account changes, such as a websocket `accountSubscribe` or a
+/// Geyser plugin stream.
+#[async_trait]
+pub trait AccountSubscriber: Send + Sync {
+    /// Streams changes of `pubkeys` until the subscription drops, which
+    /// closes the channel.
+    async fn subscribe(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> anyhow::Result<mpsc::Receiver<AccountUpdate>>;
+}
+
+/// Keeps a `ReflectAmm` up to date, by polling or from account changes, and
+/// publishes it after each update.
+pub struct ReflectUpdater<F> {
+    fetcher: F,
+    amm: ReflectAmm,
+    clock_ref: ClockRef,
+    config: UpdaterConfig,
+    /// Slot of the newest data of each watched account. The AMM caches the
+    /// data itself.
+    account_slots: HashMap<Pubkey, u64>,
+    amm_sender: watch::Sender<Option<ReflectAmm>>,
+    health_sender: watch::Sender<UpdaterHealth>,
+}
+
+impl<F: AccountFetcher> ReflectUpdater<F> {
+    /// The updater drives the clock of `amm` from the clock sysvar.
+    pub fn new(fetcher: F, amm: ReflectAmm, config: UpdaterConfig) -> Self {
+        let clock_ref = ClockRef::default();
+        ReflectUpdater {
+            fetcher,
+            amm: amm.with_clock(clock_ref.clone()),
+            clock_ref,
+            config,
+            account_slots: HashMap::new(),
+            amm_sender: watch::channel(None).0,
+            health_sender: watch::channel(UpdaterHealth::default()).0,
+        }
+    }
+
+    /// The AMM after each successful update, `None` before the first one.
+    pub fn subscribe(&self) -> watch::Receiver<Option<ReflectAmm>> {
+        self.amm_sender.subscribe()
+    }
+
+    pub fn health(&self) -> watch::Receiver<UpdaterHealth> {
+        self.health_sender.subscribe()
+    }
+
+    /// Fetches the accounts and the clock, updates and publishes the AMM.
+    /// Accounts the RPC does not return keep their cached data in the AMM.
+    pub async fn update_once(&mut self) -> anyhow::Result<()> {
+        let pubkeys = self.watched_pubkeys();
+        let accounts = self.fetcher.get_multiple_accounts(&pubkeys).await?;
+        anyhow::ensure!(
+            accounts.len() == pubkeys.len(),
+            "Fetched {} accounts for {} pubkeys",
+            accounts.len(),
+            pubkeys.len()
+        );
+
+        let mut account_map: AccountMap = pubkeys
+            .iter()
+            .zip(accounts)
+            .filter_map(|(pubkey, account)| Some((*pubkey, account?)))
+            .collect();
+        let clock: Clock = account_map
+            .remove(&sysvar::clock::ID)
+            .as_ref()
+            .and_then(from_account)
+            .context("Invalid clock sysvar")?;
+        // Later changes of accounts missing here are always newer.
+        self.account_slots = pubkeys
+            .into_iter()
+            .map(|pubkey| {
+                let fetched = pubkey == sysvar::clock::ID
+                    || account_map.contains_key(&pubkey);
+                (pubkey, if fetched { clock.slot } else { 0 })
+            })
+            .collect();
+        self.clock_ref.update(clock);
+        self.refresh(&account_map)
+    }
+
+    /// Applies every queued change, then updates the AMM with the changed
+    /// accounts if it reads any of them. The AMM decodes only those and
+    /// keeps the rest. Returns whether it updated.
+    pub async fn process_updates(
+        &mut self,
+        updates: &mut mpsc::Receiver<AccountUpdate>,
+    ) -> anyhow::Result<bool> {
+        let mut changes = AccountMap::default();
+        let update = updates.recv().await.context("Subscription closed")?;
+        self.apply(update, &mut changes)?;
+        while let Ok(update) = updates.try_recv() {
+            self.apply(update, &mut changes)?;
+        }
+        if changes.is_empty() {
+            return Ok(false);
+        }
+        self.refresh(&changes)?;
+        Ok(true)
+    }
+
+    /// Updates every `interval`, backing off while updates fail. Returns
+    /// once every `subscribe` receiver is dropped, so subscribe first.
+    pub async fn run(mut self) {
+        while !self.amm_sender.is_closed() {
+            let wait = match self.update_once().await {
+                Ok(()) => {
+                    self.report_success();
+                    self.config.interval
+                }
+                Err(e) => self.report_failure(e),
+            };
+            time::sleep(wait).await;
+        }
+    }
+
+    /// Updates on account changes from `subscriber` instead of polling.
+    /// A snapshot is fetched after subscribing, and again after the
+    /// subscription drops. Returns like `run`.
+    pub async fn run_subscribed<S: AccountSubscriber>(mut self, subscriber: S) {
+        let amm_sender = self.amm_sender.clone();
+        loop {
+            let wait = tokio::select! {
+                result = self.follow(&subscriber) => match result {
+                    Ok(()) => return,
+                    Err(e) => self.report_failure(e),
+                },
+                _ = amm_sender.closed() => return,
+            };
+            time::sleep(wait).await;
+        }
+    }
+
+    /// Follows one subscription until it fails.
+    async fn follow<S: AccountSubscriber>(
+        &mut self,
+        subscriber: &S,
+    ) -> anyhow::Result<()> {
+        let mut updates = subscriber.subscribe(&self.watched_pubkeys()).await?;
+        self.update_once().await?;
+        self.report_success();
+        loop {
+            if self.process_updates(&mut updates).await? {
+                self.report_success();
+            }
+        }
+    }
+
+    /// The accounts the AMM reads and the clock sysvar.
+    fn watched_pubkeys(&self) -> Vec<Pubkey> {
+        let mut pubkeys = self.amm.get_accounts_to_update();
+        pubkeys.push(sysvar::clock::ID);
+        pubkeys
+    }
+
+    /// Adds a change the AMM needs to `changes`. The clock is shared with
+    /// the AMM, so a new clock is applied right away instead.
+    fn apply(
+        &mut self,
+        update: AccountUpdate,
+        changes: &mut AccountMap,
+    ) -> anyhow::Result<()> {
+        let AccountUpdate {
+            pubkey,
+            slot,
+            account,
+        } = update;
+        match self.account_slots.get(&pubkey) {
+            // Older than what the AMM has, or not watched.
+            Some(newest) if *newest > slot => return Ok(()),
+            None => return Ok(()),
+            Some(_) => {}
+        }
+        self.account_slots.insert(pubkey, slot);
+
+        if pubkey == sysvar::clock::ID {
+            let clock: Clock =
+                from_account(&account).context("Invalid clock sysvar")?;
+            self.clock_ref.update(clock);
+        } else {
+            changes.insert(pubkey, account);
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self, account_map: &AccountMap) -> anyhow::Result<()> {
+        self.amm.update_observed(account_map, &self.account_slots)?;
+        self.amm_sender.send_replace(Some(self.amm.clone()));
+        Ok(())
+    }
+
+    fn report_success(&self) {
+        let slot = self.clock_ref.slot.load(Ordering::Relaxed);
+        self.health_sender.send_replace(UpdaterHealth {
+            last_success_slot: Some(slot),
+            consecutive_failures: 0,
+            last_error: None,
+        });
+    }
+
+    /// Records a failure and returns how long to wait before retrying.
+    fn report_failure(&self, error: anyhow::Error) -> Duration {
+        let mut failures = 0;
+        self.health_sender.send_modify(|health| {
+            health.consecutive_failures += 1;
+            health.last_error = Some(Arc::new(error));
+            failures = health.consecutive_failures;
+        });
+        self.config.backoff(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{atomic::AtomicU32, Mutex},
+    };
+
+    use solana_sdk::account::create_account_for_test;
+
+    use super::*;
+    use crate::{error::ReflectAmmError, fixtures};
+
+    fn clock_account(slot: u64) -> Account {
+        create_account_for_test(&Clock {
+            slot,
+            unix_timestamp: 1_700_000_000,
+            ..Clock::default()
+        })
+    }
+
+    /// Serves the fixture accounts after failing `failures` times.
+    struct FixtureFetcher {
+        accounts: Mutex<AccountMap>,
+        failures: AtomicU32,
+    }
+
+    impl FixtureFetcher {
+        fn new(failures: u32) -> Self {
+            let mut accounts = fixtures::usdc_plus_account_map();
+            accounts.insert(sysvar::clock::ID, clock_account(1_000));
+            FixtureFetcher {
+                accounts: Mutex::new(accounts),
+                failures: AtomicU32::new(failures),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AccountFetcher for FixtureFetcher {
+        async fn get_multiple_accounts(
+            &self,
+            pubkeys: &[Pubkey],
+        ) -> anyhow::Result<Vec<Option<Account>>> {
+            if self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    n.checked_sub(1)
+                })
+                .is_ok()
+            {
+                anyhow::bail!("RPC unavailable");
+            }
+            let accounts = self.accounts.lock().unwrap();
+            Ok(pubkeys
+                .iter()
+                .map(|pubkey| accounts.get(pubkey).cloned())
+                .collect())
+        }
+    }
+
+    /// Hands out queued subscriptions, one per `subscribe`.
+    struct ChannelSubscriber {
+        subscriptions: Mutex<VecDeque<mpsc::Receiver<AccountUpdate>>>,
+    }
+
+    #[async_trait]
+    impl AccountSubscriber for ChannelSubscriber {
+        async fn subscribe(
+            &self,
+            _pubkeys: &[Pubkey],
+        ) -> anyhow::Result<mpsc::Receiver<AccountUpdate>> {
+            self.subscriptions
+                .lock()
+                .unwrap()
+                .pop_front()
+                .context("Subscription refused")
+        }
+    }
+
+    /// The receipt mint of `updater` with a larger supply.
+    fn minted(updater: &ReflectUpdater<FixtureFetcher>) -> (Pubkey, Account) {
+        let pubkey = updater.amm.usdc_plus_mint;
+        let mut mint =
+            updater.fetcher.accounts.lock().unwrap()[&pubkey].clone();
+        let supply = crate::token::mint_supply(&mint.data).unwrap();
+        // Mint supply
+        mint.data[36..44].copy_from_slice(&(supply + 1_000_000).to_le_bytes());
+        (pubkey, mint)
+    }
+
+    #[test]
+    fn test_updater_backoff() {
+        let config = UpdaterConfig::default();
+        assert_eq!(config.backoff(1), Duration::from_millis(500));
+        assert_eq!(config.backoff(3), Duration::from_secs(2));
+        assert_eq!(config.backoff(7), Duration::from_secs(30));
+        assert_eq!(config.backoff(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_updater_update_once() {
+        let fetcher = FixtureFetcher::new(0);
+        let controller = fixtures::usdc_plus_amm().usdc_plus_controller;
+        let controller_account = fetcher
+            .accounts
+            .lock()
+            .unwrap()
+            .remove(&controller)
+            .unwrap();
+        let mut updater = ReflectUpdater::new(
+            fetcher,
+            fixtures::usdc_plus_amm(),
+            UpdaterConfig::default(),
+        );
+        let receiver = updater.subscribe();
+
+        // The AMM has never seen the controller.
+        let err = updater.update_once().await.unwrap_err();
+        assert_eq!(ReflectAmmError::kind_of(&err), "missing_account");
+        assert!(receiver.borrow().is_none());
+
+        updater
+            .fetcher
+            .accounts
+            .lock()
+            .unwrap()
+            .insert(controller, controller_account);
+        updater.update_once().await.unwrap();
+        let amm = receiver.borrow().clone().unwrap();
+        assert_eq!(amm.last_update.unwrap().slot, 1_000);
+        assert!(amm.state.is_some());
+
+        // Once seen, an account the RPC drops keeps its cached data.
+        updater.fetcher.accounts.lock().unwrap().remove(&controller);
+        updater.update_once().await.unwrap();
+
+        // Only a newer clock: the state keeps its stamp and ages.
+        let stamp = updater.amm.last_update;
+        {
+            let mut accounts = updater.fetcher.accounts.lock().unwrap();
+            accounts.clear();
+            accounts.insert(sysvar::clock::ID, clock_account(1_005));
+        }
+        updater.update_once().await.unwrap();
+        assert_eq!(updater.amm.last_update, stamp);
+        assert_eq!(updater.amm.state_age().unwrap().slots, 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_updater_run_backs_off_and_recovers() {
+        let updater = ReflectUpdater::new(
+            FixtureFetcher::new(2),
+            fixtures::usdc_plus_amm(),
+            UpdaterConfig::default(),
+        );
+        let mut receiver = updater.subscribe();
+        let mut health = updater.health();
+        let task = tokio::spawn(updater.run());
+
+        health.changed().await.unwrap();
+        {
+            let health = health.borrow_and_update();
+            assert_eq!(health.consecutive_failures, 1);
+            assert!(health.last_error.is_some());
+        }
+        health.changed().await.unwrap();
+        assert_eq!(health.borrow_and_update().consecutive_failures, 2);
+
+        receiver.changed().await.unwrap();
+        assert!(receiver.borrow_and_update().is_some());
+        health.changed().await.unwrap();
+        let recovered = health.borrow_and_update().clone();
+        assert_eq!(recovered.consecutive_failures, 0);
+        assert_eq!(recovered.last_success_slot, Some(1_000));
+
+        drop(receiver);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_updater_processes_changed_accounts() {
+        let mut updater = ReflectUpdater::new(
+            FixtureFetcher::new(0),
+            fixtures::usdc_plus_amm(),
+            UpdaterConfig::default(),
+        );
+        let receiver = updater.subscribe();
+        updater.update_once().await.unwrap();
+        let supply = updater.amm.effective_supply;
+        let (mint_key, mint) = minted(&updater);
+
+        // A new clock, a change older than the snapshot and an account the
+        // AMM does not read leave the exchange components alone.
+        let (sender, mut updates) = mpsc::channel(8);
+        for (pubkey, slot, account) in [
+            (sysvar::clock::ID, 1_001, clock_account(1_001)),
+            (mint_key, 999, mint.clone()),
+            (Pubkey::new_unique(), 1_001, mint.clone()),
+        ] {
+            sender
+                .send(AccountUpdate {
+                    pubkey,
+                    slot,
+                    account,
+                })
+                .await
+                .unwrap();
+        }
+        assert!(!updater.process_updates(&mut updates).await.unwrap());
+        assert_eq!(updater.amm.clock.slot(), 1_001);
+        assert_eq!(updater.amm.effective_supply, supply);
+
+        sender
+            .send(AccountUpdate {
+                pubkey: mint_key,
+                slot: 1_002,
+                account: mint,
+            })
+            .await
+            .unwrap();
+        assert!(updater.process_updates(&mut updates).await.unwrap());
+        let amm = receiver.borrow().clone().unwrap();
+        assert_eq!(amm.effective_supply, supply + 1_000_000);
+        // The mint is current, the other accounts still date from the
+        // snapshot.
+        assert_eq!(amm.account_slots[&mint_key], 1_002);
+        assert_eq!(amm.last_update.unwrap().slot, 1_000);
+
+        drop(sender);
+        assert!(updater.process_updates(&mut updates).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_updater_run_subscribed_resubscribes() {
+        let (first_sender, first) = mpsc::channel(8);
+        let (second_sender, second) = mpsc::channel(8);
+        let subscriber = ChannelSubscriber {
+            subscriptions: Mutex::new(VecDeque::from([first, second])),
+        };
+        let updater = ReflectUpdater::new(
+            FixtureFetcher::new(0),
+            fixtures::usdc_plus_amm(),
+            UpdaterConfig::default(),
+        );
+        let (mint_key, mint) = minted(&updater);
+        let mut receiver = updater.subscribe();
+        let mut health = updater.health();
+        let task = tokio::spawn(updater.run_subscribed(subscriber));
+
+        // Seeded from a snapshot.
+        receiver.changed().await.unwrap();
+        let supply = receiver
+            .borrow_and_update()
+            .clone()
+            .unwrap()
+            .effective_supply;
+
+        // A dropped subscription is followed by a new one and a snapshot.
+        drop(first_sender);
+        health
+            .wait_for(|health| health.consecutive_failures == 1)
+            .await
+            .unwrap();
+        receiver.changed().await.unwrap();
+        health
+            .wait_for(|health| health.consecutive_failures == 0)
+            .await
+            .unwrap();
+
+        second_sender
+            .send(AccountUpdate {
+                pubkey: mint_key,
+                slot: 1_001,
+                account: mint,
+            })
+            .await
+            .unwrap();
+        receiver.changed().await.unwrap();
+        let amm = receiver.borrow_and_update().clone().unwrap();
+        assert_eq!(amm.effective_supply, supply + 1_000_000);
+
+        drop(receiver);
+        task.await.unwrap();
+    }
+}